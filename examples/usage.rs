@@ -6,13 +6,20 @@ use std::time::Duration;
 fn main() {
     // try initialize sound system with default device
     let mut sound_system_handle = OrbSoundSystem::run().unwrap();
-    sound_system_handle
-        .play_sound(
+    // play sound till the end
+    let outcome = sound_system_handle
+        .play_and_wait(
             "sounds/test.wav",
             SoundPriority::High,
             None,
+            Some(Duration::from_secs(10)),
         )
         .unwrap();
+    println!("Playback outcome: {:?}", outcome);
+
+    let playback = sound_system_handle
+        .play_sound("sounds/test.wav", SoundPriority::High, None)
+        .unwrap();
     thread::sleep(Duration::from_millis(500));
     // pause playback
     sound_system_handle.pause().unwrap();
@@ -21,8 +28,13 @@ fn main() {
     sound_system_handle.resume().unwrap();
     thread::sleep(Duration::from_millis(500));
     // adjust volume
-    sound_system_handle.adjust_volume(1.0).unwrap();
+    sound_system_handle.adjust_volume_db(-6.0).unwrap();
+    thread::sleep(Duration::from_millis(500));
+    sound_system_handle.mute().unwrap();
     thread::sleep(Duration::from_millis(500));
+    sound_system_handle.unmute().unwrap();
     sound_system_handle.set_volume(0.5).unwrap();
-    thread::sleep(Duration::from_secs(3));
+    // wait until the sound is over
+    let last_event = playback.events.iter().last();
+    println!("Last playback event: {:?}", last_event);
 }
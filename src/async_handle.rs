@@ -0,0 +1,452 @@
+//! Async flavor of [`OrbSoundSystemHandle`], enabled by `async` cargo feature.
+//!
+//! Futures returned here are woken directly by the sound system thread, they don't depend on any
+//! particular async runtime and work with tokio, async-std or a plain `block_on` executor.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), orb_sound::OrbSoundSystemError> {
+//! use orb_sound::handle::{PlaybackEvent, SoundPriority};
+//! use orb_sound::OrbSoundSystem;
+//!
+//! let mut handle = OrbSoundSystem::run()?.into_async();
+//! let mut events = handle.subscribe()?;
+//! // resolves once the sound is played till the end, dropped or failed
+//! let event = handle
+//!     .play_sound("path/to/sound.wav", SoundPriority::High, None)
+//!     .await?;
+//! assert!(matches!(event, PlaybackEvent::Finished));
+//! while let Some(event) = events.next().await {
+//!     println!("{:?}", event);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use std::collections::VecDeque;
+use std::future::{self, Future};
+use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
+use std::sync::mpsc::TrySendError;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll, Waker};
+use std::time::Duration;
+
+use futures_core::Stream;
+
+use crate::handle::{
+    EventSender, PlaybackEvent, PlaybackId, SoundCommand, SoundEvent, SoundPriority, SoundSource,
+    SoundSystemStatus, EVENT_CHANNEL_CAPACITY,
+};
+use crate::{OrbSoundSystemError, OrbSoundSystemHandle};
+
+/// Async handle to the sound system. Methods which wait for the system are async and take place of
+/// their blocking counterparts, the rest of [`OrbSoundSystemHandle`] methods are available through
+/// `Deref`. Can be safely cloned and moved between threads.
+///
+/// [`OrbSoundSystemHandle::play_and_wait()`] is reachable through `Deref` as well, but it blocks
+/// the calling thread until the sound is over and must not be used from async code. Await future
+/// returned by [`AsyncOrbSoundSystemHandle::play_sound()`] instead, along with timeout of your
+/// runtime if needed. Receivers returned by [`OrbSoundSystemHandle::device_events()`] block the
+/// same way, unless polled with `try_recv()`.
+#[derive(Clone)]
+pub struct AsyncOrbSoundSystemHandle {
+    handle: OrbSoundSystemHandle,
+}
+
+impl OrbSoundSystemHandle {
+    /// Convert into async flavor of the handle.
+    pub fn into_async(self) -> AsyncOrbSoundSystemHandle {
+        AsyncOrbSoundSystemHandle { handle: self }
+    }
+}
+
+impl From<OrbSoundSystemHandle> for AsyncOrbSoundSystemHandle {
+    fn from(handle: OrbSoundSystemHandle) -> Self {
+        handle.into_async()
+    }
+}
+
+impl Deref for AsyncOrbSoundSystemHandle {
+    type Target = OrbSoundSystemHandle;
+
+    fn deref(&self) -> &Self::Target {
+        &self.handle
+    }
+}
+
+impl DerefMut for AsyncOrbSoundSystemHandle {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.handle
+    }
+}
+
+impl AsyncOrbSoundSystemHandle {
+    /// Request playback of the file located by given `path`, see
+    /// [`OrbSoundSystemHandle::play_sound()`]. Request is sent right away, returned future
+    /// resolves to the final [`PlaybackEvent`] of the request. Dropping the future does not cancel
+    /// the request.
+    pub fn play_sound(
+        &mut self,
+        path: &str,
+        priority: SoundPriority,
+        max_delay: Option<Duration>,
+    ) -> PlaybackFuture {
+        self.play_source(SoundSource::File(path.to_string()), priority, max_delay)
+    }
+
+    /// Request playback of sound file data kept in memory, see
+    /// [`OrbSoundSystemHandle::play_bytes()`]. Works the same way as
+    /// [`AsyncOrbSoundSystemHandle::play_sound()`].
+    pub fn play_bytes(
+        &mut self,
+        data: impl Into<Arc<[u8]>>,
+        priority: SoundPriority,
+        max_delay: Option<Duration>,
+    ) -> PlaybackFuture {
+        self.play_source(SoundSource::Bytes(data.into()), priority, max_delay)
+    }
+
+    /// Request playback of sound file data embedded into the binary, see
+    /// [`OrbSoundSystemHandle::play_static_bytes()`]. Works the same way as
+    /// [`AsyncOrbSoundSystemHandle::play_sound()`].
+    pub fn play_static_bytes(
+        &mut self,
+        data: &'static [u8],
+        priority: SoundPriority,
+        max_delay: Option<Duration>,
+    ) -> PlaybackFuture {
+        self.play_source(SoundSource::StaticBytes(data), priority, max_delay)
+    }
+
+    /// Request playback of a sound preloaded into registered sound bank, see
+    /// [`OrbSoundSystemHandle::play_named()`]. Works the same way as
+    /// [`AsyncOrbSoundSystemHandle::play_sound()`].
+    pub fn play_named(
+        &mut self,
+        name: &str,
+        priority: SoundPriority,
+        max_delay: Option<Duration>,
+    ) -> PlaybackFuture {
+        match self.handle.named_source(name) {
+            Ok(source) => self.play_source(source, priority, max_delay),
+            Err(err) => PlaybackFuture::rejected(err),
+        }
+    }
+
+    /// Request playback of given `source`. Works the same way as
+    /// [`AsyncOrbSoundSystemHandle::play_sound()`].
+    pub fn play_source(
+        &mut self,
+        source: SoundSource,
+        priority: SoundPriority,
+        max_delay: Option<Duration>,
+    ) -> PlaybackFuture {
+        let (sender, events) = channel(None);
+        match self
+            .handle
+            .request(source, priority, max_delay, EventSender::Async(sender))
+        {
+            Ok(id) => PlaybackFuture {
+                id: Some(id),
+                state: Ok(events),
+            },
+            Err(err) => PlaybackFuture::rejected(err),
+        }
+    }
+
+    /// Returns snapshot of the system state, see [`OrbSoundSystemHandle::status()`].
+    pub async fn status(&mut self) -> Result<SoundSystemStatus, OrbSoundSystemError> {
+        let (sender, mut receiver) = channel(None);
+        self.handle
+            .send_command(SoundCommand::Status(EventSender::Async(sender)))?;
+        receiver
+            .recv()
+            .await
+            .ok_or(OrbSoundSystemError::SystemIsDown)
+    }
+
+    /// Shutdown the system, see [`OrbSoundSystemHandle::shutdown()`]. Resolves once the event loop
+    /// is over and output is completed.
+    pub async fn shutdown(&mut self) -> Result<(), OrbSoundSystemError> {
+        let (sender, mut stopped) = channel(None);
+        self.handle
+            .send_command(SoundCommand::Shutdown(EventSender::Async(sender)))?;
+        // System drops the sender if it stops before replying
+        stopped.recv().await;
+        Ok(())
+    }
+
+    /// Subscribe to events of all sounds and changes of playback state, see
+    /// [`OrbSoundSystemHandle::subscribe()`].
+    pub fn subscribe(&mut self) -> Result<EventStream, OrbSoundSystemError> {
+        let (sender, events) = channel(Some(EVENT_CHANNEL_CAPACITY));
+        self.handle
+            .send_command(SoundCommand::Subscribe(EventSender::Async(sender)))?;
+        Ok(EventStream { events })
+    }
+}
+
+/// Future returned by [`AsyncOrbSoundSystemHandle::play_sound()`]. Resolves to the final
+/// [`PlaybackEvent`] of the request (see [`PlaybackEvent::is_final()`]), to the error the request
+/// was rejected with or to [`OrbSoundSystemError::SystemIsDown`] if the system shuts down first.
+#[derive(Debug)]
+pub struct PlaybackFuture {
+    id: Option<PlaybackId>,
+    /// Events of the request or error it was rejected with, taken once returned
+    state: Result<AsyncReceiver<PlaybackEvent>, Option<OrbSoundSystemError>>,
+}
+
+impl PlaybackFuture {
+    fn rejected(err: OrbSoundSystemError) -> Self {
+        Self {
+            id: None,
+            state: Err(Some(err)),
+        }
+    }
+
+    /// Identifier of the request, e.g. to cancel it. None if the request was rejected.
+    pub fn id(&self) -> Option<PlaybackId> {
+        self.id
+    }
+}
+
+impl Future for PlaybackFuture {
+    type Output = Result<PlaybackEvent, OrbSoundSystemError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let events = match &mut self.state {
+            Ok(events) => events,
+            Err(err) => return Poll::Ready(Err(err.take().expect("polled after completion"))),
+        };
+        loop {
+            match ready!(events.poll_recv(cx)) {
+                Some(event) if event.is_final() => return Poll::Ready(Ok(event)),
+                Some(_) => continue,
+                None => return Poll::Ready(Err(OrbSoundSystemError::SystemIsDown)),
+            }
+        }
+    }
+}
+
+/// Async stream of [`SoundEvent`]s returned by [`AsyncOrbSoundSystemHandle::subscribe()`]. Ends
+/// when the system shuts down. Implements [`Stream`], so it works with `StreamExt` combinators and
+/// `select!`.
+#[derive(Debug)]
+pub struct EventStream {
+    events: AsyncReceiver<SoundEvent>,
+}
+
+impl EventStream {
+    /// Returns next event or None once the system is down.
+    pub async fn next(&mut self) -> Option<SoundEvent> {
+        self.events.recv().await
+    }
+}
+
+impl Stream for EventStream {
+    type Item = SoundEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+/// Creates channel which wakes the receiving task on send. Sending never blocks, bounded channel
+/// reports [`TrySendError::Full`] instead.
+pub(crate) fn channel<T>(capacity: Option<usize>) -> (AsyncSender<T>, AsyncReceiver<T>) {
+    let channel = Arc::new(Mutex::new(Channel {
+        queue: VecDeque::new(),
+        capacity,
+        waker: None,
+        closed: false,
+    }));
+    (AsyncSender(channel.clone()), AsyncReceiver(channel))
+}
+
+#[derive(Debug)]
+struct Channel<T> {
+    queue: VecDeque<T>,
+    capacity: Option<usize>,
+    /// Waker of the task waiting for a value
+    waker: Option<Waker>,
+    /// Set once either side is dropped
+    closed: bool,
+}
+
+/// Sending part of [`channel()`].
+#[derive(Debug)]
+pub(crate) struct AsyncSender<T>(Arc<Mutex<Channel<T>>>);
+
+impl<T> AsyncSender<T> {
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        let mut channel = self.0.lock().unwrap();
+        if channel.closed {
+            return Err(TrySendError::Disconnected(value));
+        }
+        if matches!(channel.capacity, Some(capacity) if channel.queue.len() >= capacity) {
+            return Err(TrySendError::Full(value));
+        }
+        channel.queue.push_back(value);
+        if let Some(waker) = channel.waker.take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+}
+
+impl<T> Drop for AsyncSender<T> {
+    fn drop(&mut self) {
+        let mut channel = self.0.lock().unwrap();
+        channel.closed = true;
+        if let Some(waker) = channel.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Receiving part of [`channel()`].
+#[derive(Debug)]
+pub(crate) struct AsyncReceiver<T>(Arc<Mutex<Channel<T>>>);
+
+impl<T> AsyncReceiver<T> {
+    /// Returns next value or None once sender is dropped and all values are received.
+    pub async fn recv(&mut self) -> Option<T> {
+        future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut channel = self.0.lock().unwrap();
+        if let Some(value) = channel.queue.pop_front() {
+            return Poll::Ready(Some(value));
+        }
+        if channel.closed {
+            return Poll::Ready(None);
+        }
+        channel.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for AsyncReceiver<T> {
+    fn drop(&mut self) {
+        self.0.lock().unwrap().closed = true;
+    }
+}
+
+#[cfg(test)]
+// Some of the tests decode WAV files
+#[cfg_attr(not(feature = "wav"), allow(unused_imports, dead_code))]
+mod test {
+    use std::future::{self, Future};
+    use std::pin::{pin, Pin};
+    use std::sync::mpsc::TrySendError;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread::{self, Thread};
+
+    use futures_core::Stream;
+
+    use crate::async_handle::{channel, EventStream};
+    use crate::handle::{PlaybackEvent, SoundEvent, SoundPriority};
+    use crate::system::RenderOutput;
+    use crate::{OrbSoundSystem, OrbSoundSystemError};
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    /// Minimal executor, futures of the crate must not depend on any particular runtime.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    #[test]
+    fn wake_on_send() {
+        let (sender, mut receiver) = channel(Some(2));
+        sender.try_send(1).unwrap();
+        sender.try_send(2).unwrap();
+        assert!(matches!(sender.try_send(3), Err(TrySendError::Full(3))));
+        assert_eq!(block_on(receiver.recv()), Some(1));
+        assert_eq!(block_on(receiver.recv()), Some(2));
+
+        let sending = thread::spawn(move || {
+            sender.try_send(4).unwrap();
+        });
+        assert_eq!(block_on(receiver.recv()), Some(4));
+        sending.join().unwrap();
+        // sender is dropped
+        assert_eq!(block_on(receiver.recv()), None);
+
+        let (sender, receiver) = channel(None);
+        drop(receiver);
+        assert!(matches!(
+            sender.try_send(1),
+            Err(TrySendError::Disconnected(1))
+        ));
+    }
+
+    #[test]
+    fn event_stream() {
+        let (sender, events) = channel(None);
+        let mut stream = EventStream { events };
+        sender.try_send(SoundEvent::Muted).unwrap();
+        drop(sender);
+        // polled through `Stream` trait, the way stream combinators do
+        let mut next = || block_on(future::poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)));
+        assert!(matches!(next(), Some(SoundEvent::Muted)));
+        assert!(next().is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "wav")]
+    fn play_sound() {
+        let (output, _) = RenderOutput::memory(2, 44100);
+        let mut handle = OrbSoundSystem::builder()
+            .output(output)
+            .run()
+            .unwrap()
+            .into_async();
+        let mut events = handle.subscribe().unwrap();
+
+        let playback = handle.play_sound("sounds/test.wav", SoundPriority::High, None);
+        assert!(playback.id().is_some());
+        assert!(matches!(block_on(playback), Ok(PlaybackEvent::Finished)));
+        let rejected = handle.play_sound("sounds/missing.wav", SoundPriority::High, None);
+        assert_eq!(rejected.id(), None);
+        assert!(matches!(
+            block_on(rejected),
+            Err(OrbSoundSystemError::SoundFileErr(_))
+        ));
+        assert!(block_on(handle.status()).unwrap().current.is_none());
+
+        assert!(matches!(
+            block_on(events.next()),
+            Some(SoundEvent::Queued(_))
+        ));
+        assert!(matches!(
+            block_on(events.next()),
+            Some(SoundEvent::Started(_))
+        ));
+        assert!(matches!(
+            block_on(events.next()),
+            Some(SoundEvent::Finished(_))
+        ));
+        block_on(handle.shutdown()).unwrap();
+        assert!(block_on(events.next()).is_none());
+        assert!(matches!(
+            block_on(handle.shutdown()),
+            Err(OrbSoundSystemError::SystemIsDown)
+        ));
+    }
+}
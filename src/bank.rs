@@ -0,0 +1,385 @@
+//! Bank of preloaded sounds. Sounds are decoded once and kept in memory as samples, so playing
+//! them does not involve file system access or decoding.
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rodio::Source;
+
+use crate::handle::SoundSource;
+use crate::system::sound;
+use crate::OrbSoundSystemError;
+
+/// Loudness normalization never boosts sound more than this many decibels, so quiet sounds do not
+/// bring their noise floor to the target loudness.
+const MAX_NORMALIZATION_GAIN_DB: f32 = 12.0;
+/// Sounds quieter than this many decibels relative to full scale are considered silent and are not
+/// normalized.
+const SILENCE_FLOOR_DB: f32 = -70.0;
+
+/// Bank of named sounds decoded into memory. Amount of memory taken by decoded samples is limited,
+/// least recently used sounds are evicted to make room for new ones.
+///
+/// Can be cheaply cloned, all clones share the same sounds. Register it using
+/// [`OrbSoundSystemHandle::register_sound_bank()`] and play sounds with
+/// [`OrbSoundSystemHandle::play_named()`].
+///
+/// [`OrbSoundSystemHandle::register_sound_bank()`]: crate::OrbSoundSystemHandle::register_sound_bank
+/// [`OrbSoundSystemHandle::play_named()`]: crate::OrbSoundSystemHandle::play_named
+#[derive(Clone)]
+pub struct SoundBank {
+    inner: Arc<Mutex<Bank>>,
+}
+
+struct Bank {
+    sounds: HashMap<String, BankEntry>,
+    /// Max number of bytes decoded samples may take
+    memory_limit: usize,
+    /// Number of bytes taken by decoded samples
+    memory_used: usize,
+    /// Incremented on every access, used to find least recently used sound
+    clock: u64,
+}
+
+struct BankEntry {
+    sound: PreloadedSound,
+    last_used: u64,
+}
+
+impl SoundBank {
+    /// Create empty bank which decoded samples may take up to `memory_limit` bytes.
+    pub fn new(memory_limit: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Bank {
+                sounds: HashMap::new(),
+                memory_limit,
+                memory_used: 0,
+                clock: 0,
+            })),
+        }
+    }
+
+    /// Decode sound from `source` and keep it in the bank under given `name`, replacing sound
+    /// previously loaded with the same name. Evicts least recently used sounds if there is not
+    /// enough memory. Fails if the sound can not be decoded or it alone exceeds the memory limit.
+    ///
+    /// Decoding is done on the calling thread.
+    pub fn load(&self, name: &str, source: &SoundSource) -> Result<(), OrbSoundSystemError> {
+        let reader = sound::open(source)?;
+        let channels = reader.channels();
+        let sample_rate = reader.sample_rate();
+        let samples: Arc<[i16]> = reader.collect();
+        let sound = PreloadedSound {
+            name: name.into(),
+            loudness_db: loudness_db(&samples),
+            samples,
+            channels,
+            sample_rate,
+        };
+
+        let mut bank = self.inner.lock().unwrap();
+        if sound.memory_size() > bank.memory_limit {
+            return Err(OrbSoundSystemError::SoundBankErr(format!(
+                "{}: {} bytes exceed memory limit of {} bytes",
+                name,
+                sound.memory_size(),
+                bank.memory_limit
+            )));
+        }
+        bank.remove(name);
+        while bank.memory_used + sound.memory_size() > bank.memory_limit {
+            bank.evict_least_recently_used();
+        }
+        bank.clock += 1;
+        bank.memory_used += sound.memory_size();
+        let last_used = bank.clock;
+        bank.sounds
+            .insert(name.to_string(), BankEntry { sound, last_used });
+        Ok(())
+    }
+
+    /// Remove sound with given `name` from the bank. Returns false if there was no such sound.
+    /// Requests of the sound which are already queued are not affected.
+    pub fn evict(&self, name: &str) -> bool {
+        self.inner.lock().unwrap().remove(name)
+    }
+
+    /// Returns true if the bank contains sound with given `name`.
+    pub fn contains(&self, name: &str) -> bool {
+        self.inner.lock().unwrap().sounds.contains_key(name)
+    }
+
+    /// Number of bytes taken by decoded samples of all sounds in the bank.
+    pub fn memory_used(&self) -> usize {
+        self.inner.lock().unwrap().memory_used
+    }
+
+    /// Max number of bytes decoded samples may take.
+    pub fn memory_limit(&self) -> usize {
+        self.inner.lock().unwrap().memory_limit
+    }
+
+    /// Returns sound with given `name` and marks it as recently used.
+    pub(crate) fn get(&self, name: &str) -> Option<PreloadedSound> {
+        let mut bank = self.inner.lock().unwrap();
+        bank.clock += 1;
+        let clock = bank.clock;
+        bank.sounds.get_mut(name).map(|entry| {
+            entry.last_used = clock;
+            entry.sound.clone()
+        })
+    }
+}
+
+impl Bank {
+    fn remove(&mut self, name: &str) -> bool {
+        match self.sounds.remove(name) {
+            Some(entry) => {
+                self.memory_used -= entry.sound.memory_size();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        let name = self
+            .sounds
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(name, _)| name.clone());
+        if let Some(name) = name {
+            self.remove(&name);
+        }
+    }
+}
+
+/// Sound decoded into memory. Cheap to clone, samples are shared.
+#[derive(Clone)]
+pub struct PreloadedSound {
+    name: Arc<str>,
+    /// RMS level of samples in decibels relative to full scale
+    loudness_db: f32,
+    samples: Arc<[i16]>,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl PreloadedSound {
+    /// Name the sound was loaded under.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Number of bytes taken by decoded samples.
+    pub fn memory_size(&self) -> usize {
+        self.samples.len() * mem::size_of::<i16>()
+    }
+
+    /// Loudness of the sound measured as RMS level of its samples in decibels relative to full
+    /// scale. Negative infinity for silent sound.
+    pub fn loudness_db(&self) -> f32 {
+        self.loudness_db
+    }
+
+    /// Returns linear gain bringing loudness of the sound to `target_db`, boosting it by at most
+    /// [`MAX_NORMALIZATION_GAIN_DB`]. Silent sound is left as it is.
+    pub(crate) fn normalization_gain(&self, target_db: f32) -> f32 {
+        if self.loudness_db < SILENCE_FLOOR_DB {
+            return 1.0;
+        }
+        let gain_db = (target_db - self.loudness_db).min(MAX_NORMALIZATION_GAIN_DB);
+        10f32.powf(gain_db / 20.0)
+    }
+
+    /// Returns source of samples of the sound.
+    pub(crate) fn reader(&self) -> PreloadedReader {
+        PreloadedReader {
+            sound: self.clone(),
+            position: 0,
+        }
+    }
+}
+
+impl fmt::Debug for PreloadedSound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PreloadedSound")
+            .field("name", &self.name)
+            .field("loudness_db", &self.loudness_db)
+            .field("samples", &self.samples.len())
+            .field("channels", &self.channels)
+            .field("sample_rate", &self.sample_rate)
+            .finish()
+    }
+}
+
+/// Sounds are equal if they share the same samples.
+impl PartialEq for PreloadedSound {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.samples, &other.samples)
+    }
+}
+
+impl Eq for PreloadedSound {}
+
+/// Returns RMS level of `samples` in decibels relative to full scale.
+fn loudness_db(samples: &[i16]) -> f32 {
+    if samples.is_empty() {
+        return f32::NEG_INFINITY;
+    }
+    let full_scale = i16::MAX as f64;
+    let sum_of_squares: f64 = samples
+        .iter()
+        .map(|&sample| (sample as f64 / full_scale).powi(2))
+        .sum();
+    let rms = (sum_of_squares / samples.len() as f64).sqrt();
+    (20.0 * rms.log10()) as f32
+}
+
+/// Source of samples of [`PreloadedSound`].
+pub(crate) struct PreloadedReader {
+    sound: PreloadedSound,
+    position: usize,
+}
+
+impl Iterator for PreloadedReader {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let sample = self.sound.samples.get(self.position).copied();
+        self.position += 1;
+        sample
+    }
+}
+
+impl Source for PreloadedReader {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.sound.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sound.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(not(feature = "wav"), allow(unused_imports, dead_code))]
+mod test {
+    use std::sync::Arc;
+
+    use crate::bank::{PreloadedSound, SoundBank};
+    use crate::handle::SoundSource;
+    use crate::system::sound;
+    use crate::OrbSoundSystemError;
+
+    fn test_wav() -> SoundSource {
+        SoundSource::File("sounds/test.wav".to_string())
+    }
+
+    fn preloaded(samples: Vec<i16>) -> PreloadedSound {
+        PreloadedSound {
+            name: "test".into(),
+            loudness_db: super::loudness_db(&samples),
+            samples: Arc::from(samples),
+            channels: 1,
+            sample_rate: 48000,
+        }
+    }
+
+    // Tests decode WAV files
+    #[test]
+    #[cfg(feature = "wav")]
+    fn load() {
+        let bank = SoundBank::new(usize::MAX);
+        bank.load("test", &test_wav()).unwrap();
+        assert!(bank.contains("test"));
+        let sound = bank.get("test").unwrap();
+        assert_eq!(sound.name(), "test");
+        assert_eq!(bank.memory_used(), sound.memory_size());
+        // samples are the same as decoded from file
+        assert!(sound.reader().eq(sound::open(&test_wav()).unwrap()));
+
+        assert!(matches!(
+            bank.load(
+                "missing",
+                &SoundSource::File("sounds/missing.wav".to_string())
+            ),
+            Err(OrbSoundSystemError::SoundFileErr(_))
+        ));
+        assert!(bank.evict("test"));
+        assert!(!bank.evict("test"));
+        assert_eq!(bank.memory_used(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "wav")]
+    fn loudness() {
+        assert_eq!(super::loudness_db(&[]), f32::NEG_INFINITY);
+        assert_eq!(super::loudness_db(&[0, 0]), f32::NEG_INFINITY);
+        assert_eq!(super::loudness_db(&[i16::MAX, -i16::MAX]), 0.0);
+        // square wave at half of full scale is 6dB quieter
+        let half = i16::MAX / 2;
+        assert!((super::loudness_db(&[half, -half]) + 6.02).abs() < 0.01);
+
+        let bank = SoundBank::new(usize::MAX);
+        bank.load("test", &test_wav()).unwrap();
+        let sound = bank.get("test").unwrap();
+        let gain = sound.normalization_gain(sound.loudness_db() - 6.0);
+        assert!((gain - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn quiet_sound_gain() {
+        // boost of sound 40dB below full scale is limited
+        let quiet = preloaded(vec![327, -327]);
+        assert!((quiet.loudness_db() + 40.0).abs() < 0.1);
+        let gain = quiet.normalization_gain(-20.0);
+        assert!((gain - 10f32.powf(super::MAX_NORMALIZATION_GAIN_DB / 20.0)).abs() < 0.01);
+        // attenuation is not limited
+        assert!((quiet.normalization_gain(-60.0) - 0.1).abs() < 0.01);
+
+        // silence and noise below the floor are left as they are
+        assert_eq!(preloaded(vec![0; 4]).normalization_gain(-20.0), 1.0);
+        assert_eq!(preloaded(vec![1, -1]).normalization_gain(-20.0), 1.0);
+    }
+
+    #[test]
+    #[cfg(feature = "wav")]
+    fn eviction() {
+        let size = SoundBank::new(usize::MAX);
+        size.load("test", &test_wav()).unwrap();
+        let size = size.memory_used();
+
+        let bank = SoundBank::new(size * 2);
+        bank.load("first", &test_wav()).unwrap();
+        bank.load("second", &test_wav()).unwrap();
+        // reloading sound with the same name does not take more memory
+        bank.load("second", &test_wav()).unwrap();
+        assert_eq!(bank.memory_used(), size * 2);
+        assert!(bank.contains("first"));
+
+        // "second" is the least recently used now
+        bank.get("first").unwrap();
+        bank.load("third", &test_wav()).unwrap();
+        assert!(bank.contains("first"));
+        assert!(!bank.contains("second"));
+        assert!(bank.contains("third"));
+        assert_eq!(bank.memory_used(), size * 2);
+
+        let bank = SoundBank::new(size - 1);
+        assert!(matches!(
+            bank.load("test", &test_wav()),
+            Err(OrbSoundSystemError::SoundBankErr(_))
+        ));
+    }
+}
@@ -0,0 +1,128 @@
+//! Source of time used by the system and its handles to schedule sounds. Replacing the clock with
+//! [`ManualClock`] allows to simulate play deadlines running out without waiting for them.
+use std::fmt::Debug;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// How often event loop checks manual clock which may be advanced at any moment
+const MANUAL_CLOCK_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Source of current time. Set with [`OrbSoundSystemBuilder::clock()`], [`SystemClock`] is used
+/// by default.
+///
+/// [`OrbSoundSystemBuilder::clock()`]: crate::system::OrbSoundSystemBuilder::clock
+pub trait Clock: Debug + Send + Sync {
+    /// Returns current time.
+    fn now(&self) -> Instant;
+
+    /// Returns longest time event loop may wait without checking the clock. Clocks which time
+    /// does not flow on its own return some interval, so that changes of their time are noticed.
+    fn poll_interval(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Clock returning real time.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Clock which time stands still until advanced, for tests. Event loop checks it every
+/// millisecond, so changes of its time take effect shortly after [`ManualClock::advance()`] call.
+#[derive(Debug)]
+pub struct ManualClock {
+    now: Mutex<Instant>,
+}
+
+impl ManualClock {
+    /// Create clock stopped at current time.
+    pub fn new() -> Self {
+        Self {
+            now: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Move time forward by given `duration`.
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+
+    fn poll_interval(&self) -> Option<Duration> {
+        Some(MANUAL_CLOCK_POLL_INTERVAL)
+    }
+}
+
+#[cfg(test)]
+// Some of the tests decode WAV files
+#[cfg_attr(not(feature = "wav"), allow(unused_imports, dead_code))]
+mod test {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::clock::{Clock, ManualClock};
+    use crate::handle::{PlaybackEvent, SoundPriority};
+    use crate::system::{OrbSoundSystem, RenderOutput};
+
+    #[test]
+    fn advance() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        assert_eq!(clock.now(), start);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(clock.now(), start + Duration::from_secs(1));
+    }
+
+    #[test]
+    #[cfg(feature = "wav")]
+    fn expire_with_manual_clock() {
+        let clock = Arc::new(ManualClock::new());
+        let (output, _) = RenderOutput::memory(2, 44100);
+        let mut handle = OrbSoundSystem::builder()
+            .output(output)
+            .clock(clock.clone())
+            .run()
+            .unwrap();
+        handle.pause().unwrap();
+        let playback = handle
+            .play_sound(
+                "sounds/test.wav",
+                SoundPriority::Default,
+                Some(Duration::from_secs(10)),
+            )
+            .unwrap();
+        assert!(matches!(playback.events.recv(), Ok(PlaybackEvent::Queued)));
+        // first sound is not played out while paused, so the second one waits in the queue until
+        // its deadline passes, which happens only once clock is advanced
+        let blocked = handle
+            .play_sound(
+                "sounds/test.wav",
+                SoundPriority::Default,
+                Some(Duration::from_secs(10)),
+            )
+            .unwrap();
+        assert!(matches!(blocked.events.recv(), Ok(PlaybackEvent::Queued)));
+        clock.advance(Duration::from_secs(11));
+        assert!(matches!(
+            blocked.events.recv_timeout(Duration::from_secs(1)),
+            Ok(PlaybackEvent::Expired)
+        ));
+        handle.shutdown().unwrap();
+    }
+}
@@ -1,64 +1,419 @@
 use std::cmp::Ordering;
-use std::sync::mpsc::Sender;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::sync::mpsc::{
+    self, Receiver, RecvTimeoutError, SendError, Sender, SyncSender, TrySendError,
+};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
+use crate::bank::{PreloadedSound, SoundBank};
+use crate::clock::Clock;
+use crate::system::sound;
 use crate::OrbSoundSystemError;
 
+/// Name of the layer which always exists. Handles returned by the system play sounds on it.
+pub const DEFAULT_LAYER: &str = "default";
+
 /// Handle to the sound system. All communication with sound system is done using methods of this
 /// type. Can be safely cloned and moved between threads.
+///
+/// Every handle plays sounds on a layer, [`DEFAULT_LAYER`] unless another one is selected with
+/// [`OrbSoundSystemHandle::layer()`]. Commands managing the queue and the current sound apply to
+/// the layer of the handle, while volume and pause commands apply to the whole output.
 #[derive(Clone)]
 pub struct OrbSoundSystemHandle {
-    pub(crate) command_sender: Sender<SoundCommand>,
+    /// Sender shared between all clones of the handle. The system keeps only a weak reference to
+    /// it, so the channel is closed once all handles are dropped
+    pub(crate) command_sender: Arc<Sender<SoundCommand>>,
+    /// Counter shared between all clones of the handle, used to generate [`PlaybackId`]s
+    next_playback_id: Arc<AtomicU64>,
+    /// Bank used by [`OrbSoundSystemHandle::play_named()`], shared between all clones of the handle
+    sound_bank: Arc<Mutex<Option<SoundBank>>>,
+    /// Clock shared with the system, used to compute play deadlines
+    clock: Arc<dyn Clock>,
+    /// Layer sounds are played on
+    layer: Arc<str>,
+    /// Gain applied to requested sounds
+    gain: f32,
+    /// How requested sounds are repeated and silence between repeats
+    repeat: Repeat,
+    repeat_gap: Duration,
+    /// Names of all layers of the system
+    layers: Arc<[Arc<str>]>,
 }
 
 impl OrbSoundSystemHandle {
+    pub(crate) fn new(command_sender: Sender<SoundCommand>, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            command_sender: Arc::new(command_sender),
+            next_playback_id: Arc::new(AtomicU64::new(0)),
+            sound_bank: Arc::new(Mutex::new(None)),
+            clock,
+            layer: DEFAULT_LAYER.into(),
+            gain: 1.0,
+            repeat: Repeat::Once,
+            repeat_gap: Duration::ZERO,
+            layers: Arc::new([DEFAULT_LAYER.into()]),
+        }
+    }
+
+    /// Set names of all layers of the system.
+    pub(crate) fn with_layers(mut self, layers: Vec<Arc<str>>) -> Self {
+        self.layers = layers.into();
+        self
+    }
+
+    /// Returns clone of the handle which plays sounds on the layer with given `name`. Returns
+    /// [`OrbSoundSystemError::LayerNotFound`] if the system has no such layer, see
+    /// [`OrbSoundSystemBuilder::layer()`].
+    ///
+    /// [`OrbSoundSystemBuilder::layer()`]: crate::system::OrbSoundSystemBuilder::layer
+    pub fn layer(&self, name: &str) -> Result<Self, OrbSoundSystemError> {
+        let layer = self
+            .layers
+            .iter()
+            .find(|layer| ***layer == *name)
+            .ok_or_else(|| OrbSoundSystemError::LayerNotFound(name.to_string()))?;
+        Ok(Self {
+            layer: layer.clone(),
+            ..self.clone()
+        })
+    }
+
+    /// Returns name of the layer sounds are played on.
+    pub fn layer_name(&self) -> &str {
+        &self.layer
+    }
+
+    /// Returns clone of the handle which plays sounds with given linear `gain`, e.g. to match
+    /// loudness of assets coming from different sources. Applied to samples of every requested
+    /// sound on top of loudness normalization, see [`OrbSoundSystemBuilder::normalize_loudness()`].
+    ///
+    /// [`OrbSoundSystemBuilder::normalize_loudness()`]: crate::system::OrbSoundSystemBuilder::normalize_loudness
+    pub fn with_gain(&self, gain: f32) -> Self {
+        Self {
+            gain,
+            ..self.clone()
+        }
+    }
+
+    /// Returns clone of the handle which plays every requested sound as many times as `repeat`
+    /// says, with `gap` of silence between repeats. Repeats follow each other without gaps in the
+    /// output other than `gap` itself. A sound repeated [`Repeat::Forever`] plays until it is
+    /// cancelled, e.g. with [`OrbSoundSystemHandle::cancel()`] or [`OrbSoundSystemHandle::stop()`].
+    pub fn with_repeat(&self, repeat: Repeat, gap: Duration) -> Self {
+        Self {
+            repeat,
+            repeat_gap: gap,
+            ..self.clone()
+        }
+    }
+
     /// Request playback of the file located by given `path`. Usually it plays immediately but
     /// if there are multiple threads using this method concurrently, final order of played files
     /// will be determined based on `priority` and `max_delay` parameters. Files are never played
-    /// simultaneously.
+    /// simultaneously on the same layer.
     ///
     /// It is guaranteed that file will not be played after deadline specified by `max_delay` duration.
+    ///
+    /// Returns [`Playback`] which identifies the request and receives its [`PlaybackEvent`]s. The
+    /// file is checked before it gets queued, [`OrbSoundSystemError::SoundFileErr`] is returned if
+    /// it does not exist or its format is not supported.
     pub fn play_sound(
         &mut self,
         path: &str,
         priority: SoundPriority,
         max_delay: Option<Duration>,
-    ) -> Result<(), OrbSoundSystemError> {
+    ) -> Result<Playback, OrbSoundSystemError> {
+        self.play_source(SoundSource::File(path.to_string()), priority, max_delay)
+    }
+
+    /// Request playback of sound file data kept in memory. Works the same way as
+    /// [`OrbSoundSystemHandle::play_sound()`].
+    pub fn play_bytes(
+        &mut self,
+        data: impl Into<Arc<[u8]>>,
+        priority: SoundPriority,
+        max_delay: Option<Duration>,
+    ) -> Result<Playback, OrbSoundSystemError> {
+        self.play_source(SoundSource::Bytes(data.into()), priority, max_delay)
+    }
+
+    /// Request playback of sound file data embedded into the binary, e.g. with `include_bytes!`.
+    /// Unlike [`OrbSoundSystemHandle::play_bytes()`] the data is not copied. Works the same way as
+    /// [`OrbSoundSystemHandle::play_sound()`].
+    pub fn play_static_bytes(
+        &mut self,
+        data: &'static [u8],
+        priority: SoundPriority,
+        max_delay: Option<Duration>,
+    ) -> Result<Playback, OrbSoundSystemError> {
+        self.play_source(SoundSource::StaticBytes(data), priority, max_delay)
+    }
+
+    /// Request playback of a sound preloaded into registered [`SoundBank`] under given `name`.
+    /// Samples are streamed from memory, so the sound starts without touching file system. Works
+    /// the same way as [`OrbSoundSystemHandle::play_sound()`], but returns
+    /// [`OrbSoundSystemError::SoundBankErr`] if there is no such sound in the bank.
+    pub fn play_named(
+        &mut self,
+        name: &str,
+        priority: SoundPriority,
+        max_delay: Option<Duration>,
+    ) -> Result<Playback, OrbSoundSystemError> {
+        let source = self.named_source(name)?;
+        self.play_source(source, priority, max_delay)
+    }
+
+    /// Returns sound preloaded into registered bank under given `name`.
+    pub(crate) fn named_source(&self, name: &str) -> Result<SoundSource, OrbSoundSystemError> {
+        let sound = self
+            .sound_bank
+            .lock()
+            .unwrap()
+            .as_ref()
+            .ok_or_else(|| OrbSoundSystemError::SoundBankErr("No sound bank registered".into()))?
+            .get(name)
+            .ok_or_else(|| {
+                OrbSoundSystemError::SoundBankErr(format!("{}: no such sound in the bank", name))
+            })?;
+        Ok(SoundSource::Preloaded(sound))
+    }
+
+    /// Register bank used by [`OrbSoundSystemHandle::play_named()`]. Bank is shared between all
+    /// clones of the handle, registering another one replaces it.
+    pub fn register_sound_bank(&mut self, bank: SoundBank) {
+        *self.sound_bank.lock().unwrap() = Some(bank);
+    }
+
+    /// Request playback of given `source`. Works the same way as
+    /// [`OrbSoundSystemHandle::play_sound()`].
+    pub fn play_source(
+        &mut self,
+        source: SoundSource,
+        priority: SoundPriority,
+        max_delay: Option<Duration>,
+    ) -> Result<Playback, OrbSoundSystemError> {
+        let (event_sender, events) = mpsc::channel();
+        let id = self.request(source, priority, max_delay, event_sender.into())?;
+        Ok(Playback { id, events })
+    }
+
+    /// Play the file located by given `path` and block until it is played till the end, dropped
+    /// or failed. Works the same way as [`OrbSoundSystemHandle::play_sound()`].
+    ///
+    /// Waits for at most `timeout` if given. Request is cancelled on timeout, so the sound does
+    /// not play after the caller has stopped waiting for it.
+    pub fn play_and_wait(
+        &mut self,
+        path: &str,
+        priority: SoundPriority,
+        max_delay: Option<Duration>,
+        timeout: Option<Duration>,
+    ) -> Result<PlaybackOutcome, OrbSoundSystemError> {
+        let playback = self.play_sound(path, priority, max_delay)?;
+        let wait_until = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let event = match wait_until {
+                Some(wait_until) => {
+                    let timeout = wait_until.saturating_duration_since(Instant::now());
+                    match playback.events.recv_timeout(timeout) {
+                        Ok(event) => event,
+                        Err(RecvTimeoutError::Timeout) => {
+                            self.cancel(playback.id)?;
+                            return Ok(PlaybackOutcome::TimedOut);
+                        }
+                        Err(RecvTimeoutError::Disconnected) => {
+                            return Err(OrbSoundSystemError::SystemIsDown)
+                        }
+                    }
+                }
+                None => playback
+                    .events
+                    .recv()
+                    .map_err(|_| OrbSoundSystemError::SystemIsDown)?,
+            };
+            match event {
+                PlaybackEvent::Queued | PlaybackEvent::Started => continue,
+                PlaybackEvent::Finished => return Ok(PlaybackOutcome::Finished),
+                PlaybackEvent::Expired => return Ok(PlaybackOutcome::Expired),
+                PlaybackEvent::Cancelled => return Ok(PlaybackOutcome::Cancelled),
+                PlaybackEvent::Failed(err) => return Ok(PlaybackOutcome::Failed(err)),
+            }
+        }
+    }
+
+    /// Check given `source` and queue it, lifecycle events of the request are sent to
+    /// `event_sender`.
+    pub(crate) fn request(
+        &mut self,
+        source: SoundSource,
+        priority: SoundPriority,
+        max_delay: Option<Duration>,
+        event_sender: EventSender<PlaybackEvent>,
+    ) -> Result<PlaybackId, OrbSoundSystemError> {
+        sound::open(&source)?;
+        let id = PlaybackId(self.next_playback_id.fetch_add(1, AtomicOrdering::Relaxed));
         self.send_command(SoundCommand::PlaySound(PlaySoundCommand {
-            path: path.to_string(),
+            id,
+            source,
             priority,
-            play_deadline: max_delay.map(|delay| Instant::now() + delay),
-        }))
+            play_deadline: max_delay.map(|delay| self.clock.now() + delay),
+            layer: self.layer.clone(),
+            gain: self.gain,
+            repeat: self.repeat,
+            repeat_gap: self.repeat_gap,
+            event_sender,
+        }))?;
+        Ok(id)
     }
 
-    /// Set particular volume. If not changed, volume of a sound is equal to 1.0. Thus setting
-    /// volume to 2.0 will make it twice lauder.
+    /// Set particular volume of the whole output as linear gain. If not changed, volume of a sound
+    /// is equal to 1.0. Thus setting volume to 2.0 will make it twice lauder. Volume is kept within
+    /// [`VolumeLimits`]. Loud sounds clip at such volume unless output limiter is enabled with
+    /// [`OrbSoundSystemBuilder::limiter()`].
+    ///
+    /// [`VolumeLimits`]: crate::system::VolumeLimits
+    /// [`OrbSoundSystemBuilder::limiter()`]: crate::system::OrbSoundSystemBuilder::limiter
     pub fn set_volume(&mut self, value: f32) -> Result<(), OrbSoundSystemError> {
         self.send_command(SoundCommand::SetVolume(value))
     }
 
-    /// Adjust volume by given amount. Positive to make it lauder, negative to make it quieter.
+    /// Set particular volume of the whole output in decibels, 0dB being the volume sounds are
+    /// recorded with. Volume is kept within [`VolumeLimits`].
+    ///
+    /// [`VolumeLimits`]: crate::system::VolumeLimits
+    pub fn set_volume_db(&mut self, db: f32) -> Result<(), OrbSoundSystemError> {
+        self.send_command(SoundCommand::SetVolumeDb(db))
+    }
+
+    /// Adjust volume by given amount of decibels. Positive to make it lauder, negative to make it
+    /// quieter. Adjusting never raises volume above safe maximum of [`VolumeLimits`].
+    ///
+    /// [`VolumeLimits`]: crate::system::VolumeLimits
+    pub fn adjust_volume_db(&mut self, delta_db: f32) -> Result<(), OrbSoundSystemError> {
+        self.send_command(SoundCommand::AdjustVolumeDb(delta_db))
+    }
+
+    /// Adjust volume by given amount of linear gain. Positive to make it lauder, negative to make
+    /// it quieter. Works the same way as [`OrbSoundSystemHandle::adjust_volume_db()`].
+    #[deprecated(note = "use `adjust_volume_db`, which changes volume in decibels")]
     pub fn adjust_volume(&mut self, delta: f32) -> Result<(), OrbSoundSystemError> {
         self.send_command(SoundCommand::AdjustVolume(delta))
     }
 
-    /// Pause playback. Does nothing if already paused.
+    /// Silence the whole output. Volume is remembered and restored by
+    /// [`OrbSoundSystemHandle::unmute()`]. Does nothing if already muted.
+    pub fn mute(&mut self) -> Result<(), OrbSoundSystemError> {
+        self.send_command(SoundCommand::Mute)
+    }
+
+    /// Restore volume of muted output. Does nothing if not muted.
+    pub fn unmute(&mut self) -> Result<(), OrbSoundSystemError> {
+        self.send_command(SoundCommand::Unmute)
+    }
+
+    /// Pause playback of all layers. Does nothing if already paused.
     pub fn pause(&mut self) -> Result<(), OrbSoundSystemError> {
         self.send_command(SoundCommand::Pause)
     }
 
-    /// Resume playback. Does nothing if not paused.
+    /// Resume playback of all layers. Does nothing if not paused.
     pub fn resume(&mut self) -> Result<(), OrbSoundSystemError> {
         self.send_command(SoundCommand::Resume)
     }
 
-    /// Shutdown the system by stopping its event loop. Using handle after this call will return
+    /// Set volume of the layer of the handle. Applied on top of the volume of the whole output.
+    pub fn set_layer_volume(&mut self, value: f32) -> Result<(), OrbSoundSystemError> {
+        self.send_command(SoundCommand::SetLayerVolume(self.layer.clone(), value))
+    }
+
+    /// Pause playback of the layer of the handle, other layers keep playing. Does nothing if
+    /// already paused.
+    pub fn pause_layer(&mut self) -> Result<(), OrbSoundSystemError> {
+        self.send_command(SoundCommand::PauseLayer(self.layer.clone()))
+    }
+
+    /// Resume playback of the layer of the handle. Does nothing if not paused.
+    pub fn resume_layer(&mut self) -> Result<(), OrbSoundSystemError> {
+        self.send_command(SoundCommand::ResumeLayer(self.layer.clone()))
+    }
+
+    /// Cancel playback request identified by `id` on any layer. Removes the sound from the queue or stops it if
+    /// it is currently playing. Does nothing if the sound has already been played.
+    pub fn cancel(&mut self, id: PlaybackId) -> Result<(), OrbSoundSystemError> {
+        self.send_command(SoundCommand::Cancel(id))
+    }
+
+    /// Cancel all requests of the file located by given `path` on all layers, both queued and
+    /// currently playing.
+    pub fn cancel_path(&mut self, path: &str) -> Result<(), OrbSoundSystemError> {
+        self.send_command(SoundCommand::CancelPath(path.to_string()))
+    }
+
+    /// Cancel all requests of the layer with given `priority` or lower, both queued and currently
+    /// playing.
+    pub fn cancel_priority(&mut self, priority: SoundPriority) -> Result<(), OrbSoundSystemError> {
+        self.send_command(SoundCommand::CancelPriority(self.layer.clone(), priority))
+    }
+
+    /// Cancel all requests queued on the layer. Currently playing sound is not affected.
+    pub fn clear_queue(&mut self) -> Result<(), OrbSoundSystemError> {
+        self.send_command(SoundCommand::ClearQueue(self.layer.clone()))
+    }
+
+    /// Stop sound currently playing on the layer. Next sound from the queue of the layer (if any)
+    /// starts playing.
+    pub fn stop(&mut self) -> Result<(), OrbSoundSystemError> {
+        self.send_command(SoundCommand::Stop(self.layer.clone()))
+    }
+
+    /// Returns snapshot of the system state: currently playing sound, volume, whether playback is
+    /// paused and queued requests. Blocks until the system replies.
+    pub fn status(&mut self) -> Result<SoundSystemStatus, OrbSoundSystemError> {
+        let (sender, receiver) = mpsc::channel();
+        self.send_command(SoundCommand::Status(sender.into()))?;
+        receiver
+            .recv()
+            .map_err(|_| OrbSoundSystemError::SystemIsDown)
+    }
+
+    /// Subscribe to events of all sounds and changes of playback state. Events are dropped if the
+    /// receiver falls behind by more than [`EVENT_CHANNEL_CAPACITY`] events, subscriber gets
+    /// [`SoundEvent::Missed`] once it catches up. Subscription ends when the receiver is dropped.
+    pub fn subscribe(&mut self) -> Result<Receiver<SoundEvent>, OrbSoundSystemError> {
+        let (sender, receiver) = mpsc::sync_channel(EVENT_CHANNEL_CAPACITY);
+        self.send_command(SoundCommand::Subscribe(sender.into()))?;
+        Ok(receiver)
+    }
+
+    /// Subscribe to output device events. System reports when output stream fails, e.g. because
+    /// the device was unplugged, and when the device is reopened. While device is lost sounds do
+    /// not progress and queued ones may expire, playback continues once device is reopened.
+    /// Events are dropped if the receiver falls behind by more than [`EVENT_CHANNEL_CAPACITY`]
+    /// events.
+    pub fn device_events(&mut self) -> Result<Receiver<DeviceEvent>, OrbSoundSystemError> {
+        let (sender, receiver) = mpsc::sync_channel(EVENT_CHANNEL_CAPACITY);
+        self.send_command(SoundCommand::SubscribeDeviceEvents(sender.into()))?;
+        Ok(receiver)
+    }
+
+    /// Shutdown the system by stopping its event loop. Returns once the loop is over and output is
+    /// completed, e.g. rendered WAV file is finalized. Using handle after this call will return
     /// error.
     pub fn shutdown(&mut self) -> Result<(), OrbSoundSystemError> {
-        self.send_command(SoundCommand::Shutdown)
+        let (sender, stopped) = mpsc::channel();
+        self.send_command(SoundCommand::Shutdown(sender.into()))?;
+        // System drops the sender if it stops before replying
+        let _ = stopped.recv();
+        Ok(())
     }
 
-    fn send_command(&mut self, command: SoundCommand) -> Result<(), OrbSoundSystemError> {
+    pub(crate) fn send_command(
+        &mut self,
+        command: SoundCommand,
+    ) -> Result<(), OrbSoundSystemError> {
         self.command_sender
             .send(command)
             .map_err(|_| OrbSoundSystemError::SystemIsDown)
@@ -70,21 +425,368 @@ impl OrbSoundSystemHandle {
 pub(crate) enum SoundCommand {
     PlaySound(PlaySoundCommand),
     SetVolume(f32),
+    SetVolumeDb(f32),
     AdjustVolume(f32),
+    AdjustVolumeDb(f32),
+    Mute,
+    Unmute,
     Pause,
     Resume,
-    Shutdown,
+    SetLayerVolume(Arc<str>, f32),
+    PauseLayer(Arc<str>),
+    ResumeLayer(Arc<str>),
+    Cancel(PlaybackId),
+    CancelPath(String),
+    CancelPriority(Arc<str>, SoundPriority),
+    ClearQueue(Arc<str>),
+    Stop(Arc<str>),
+    Status(EventSender<SoundSystemStatus>),
+    Subscribe(EventSender<SoundEvent>),
+    SubscribeDeviceEvents(EventSender<DeviceEvent>),
+    /// Sent by output stream when it fails, only to wake event loop up
+    DeviceError,
+    Shutdown(EventSender<()>),
+}
+
+/// Channel the system uses to send events and replies back to the handle. Never blocks the event
+/// loop: bounded channels report [`TrySendError::Full`] instead of waiting. Not a part of command
+/// identity, so all senders are considered equal.
+pub(crate) enum EventSender<T> {
+    Unbounded(Sender<T>),
+    Bounded(SyncSender<T>),
+    #[cfg(feature = "async")]
+    Async(crate::async_handle::AsyncSender<T>),
+}
+
+impl<T> EventSender<T> {
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        match self {
+            EventSender::Unbounded(sender) => sender
+                .send(value)
+                .map_err(|SendError(value)| TrySendError::Disconnected(value)),
+            EventSender::Bounded(sender) => sender.try_send(value),
+            #[cfg(feature = "async")]
+            EventSender::Async(sender) => sender.try_send(value),
+        }
+    }
+}
+
+impl<T> From<Sender<T>> for EventSender<T> {
+    fn from(sender: Sender<T>) -> Self {
+        EventSender::Unbounded(sender)
+    }
+}
+
+impl<T> From<SyncSender<T>> for EventSender<T> {
+    fn from(sender: SyncSender<T>) -> Self {
+        EventSender::Bounded(sender)
+    }
+}
+
+impl<T> fmt::Debug for EventSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EventSender")
+    }
+}
+
+impl<T> PartialEq for EventSender<T> {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+/// Identifier of a playback request. Unique within a sound system.
+#[derive(PartialOrd, PartialEq, Ord, Eq, Hash, Debug, Clone, Copy)]
+pub struct PlaybackId(pub(crate) u64);
+
+/// Playback request returned by [`OrbSoundSystemHandle::play_sound()`].
+#[derive(Debug)]
+pub struct Playback {
+    /// Identifier of the request
+    pub id: PlaybackId,
+    /// Lifecycle events of the request. Channel gets disconnected after final event
+    /// (see [`PlaybackEvent::is_final()`]) or when the system shuts down.
+    pub events: Receiver<PlaybackEvent>,
+}
+
+/// Lifecycle event of a playback request.
+#[derive(Debug)]
+pub enum PlaybackEvent {
+    /// Request was accepted by the system and put into the queue
+    Queued,
+    /// Sound started playing
+    Started,
+    /// Sound was played till the end
+    Finished,
+    /// Sound was dropped from the queue because its play deadline has passed
+    Expired,
+    /// Request was cancelled before sound was played till the end
+    Cancelled,
+    /// Sound could not be played
+    Failed(OrbSoundSystemError),
+}
+
+impl PlaybackEvent {
+    /// Returns true if no other events will follow this one.
+    pub fn is_final(&self) -> bool {
+        !matches!(self, PlaybackEvent::Queued | PlaybackEvent::Started)
+    }
+}
+
+/// Result of [`OrbSoundSystemHandle::play_and_wait()`].
+#[derive(Debug)]
+pub enum PlaybackOutcome {
+    /// Sound was played till the end
+    Finished,
+    /// Sound was dropped from the queue because its play deadline has passed
+    Expired,
+    /// Request was cancelled before sound was played till the end
+    Cancelled,
+    /// Sound could not be played
+    Failed(OrbSoundSystemError),
+    /// Sound was not over before timeout, request was cancelled
+    TimedOut,
+}
+
+/// Max number of events waiting in the channel returned by [`OrbSoundSystemHandle::subscribe()`].
+pub const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+/// Event of the whole system, see [`OrbSoundSystemHandle::subscribe()`].
+#[derive(Debug, Clone)]
+pub enum SoundEvent {
+    /// Request was put into the queue
+    Queued(SoundInfo),
+    /// Sound started playing
+    Started(SoundInfo),
+    /// Sound was played till the end
+    Finished(SoundInfo),
+    /// Sound was dropped from the queue because its play deadline has passed
+    Expired(SoundInfo),
+    /// Request was cancelled before sound was played till the end
+    Cancelled(SoundInfo),
+    /// Sound could not be played. Contains error description
+    Failed(SoundInfo, String),
+    /// Volume of the whole output was changed. Contains new volume as linear gain
+    VolumeChanged(f32),
+    /// Whole output was muted
+    Muted,
+    /// Whole output was unmuted
+    Unmuted,
+    /// Playback of all layers was paused
+    Paused,
+    /// Playback of all layers was resumed
+    Resumed,
+    /// Volume of a layer was changed. Contains layer name and its new volume
+    LayerVolumeChanged(Arc<str>, f32),
+    /// Playback of a layer was paused. Contains layer name
+    LayerPaused(Arc<str>),
+    /// Playback of a layer was resumed. Contains layer name
+    LayerResumed(Arc<str>),
+    /// Output device state changed
+    Device(DeviceEvent),
+    /// Subscriber was too slow and given number of events were dropped
+    Missed(u64),
+}
+
+/// Sound which [`SoundEvent`] is about.
+#[derive(Debug, Clone)]
+pub struct SoundInfo {
+    /// Identifier of the request
+    pub id: PlaybackId,
+    /// Requested sound
+    pub source: SoundSource,
+    /// Sound priority
+    pub priority: SoundPriority,
+    /// Layer the sound is played on
+    pub layer: Arc<str>,
+}
+
+/// Snapshot of the system state returned by [`OrbSoundSystemHandle::status()`].
+#[derive(Debug, Clone)]
+pub struct SoundSystemStatus {
+    /// Sound being played on the default layer, if any
+    pub current: Option<PlayingSoundStatus>,
+    /// Current volume of the whole output as linear gain, regardless of mute state
+    pub volume: f32,
+    /// Current volume of the whole output in decibels, regardless of mute state
+    pub volume_db: f32,
+    /// Whether the whole output is muted
+    pub muted: bool,
+    /// Whether playback of all layers is paused
+    pub paused: bool,
+    /// Whether output is discarded by [`NullOutput`] because none of the sound devices could be
+    /// opened, so nothing is audible. See [`OrbSoundSystemBuilder::require_device()`].
+    ///
+    /// [`NullOutput`]: crate::system::NullOutput
+    /// [`OrbSoundSystemBuilder::require_device()`]: crate::system::OrbSoundSystemBuilder::require_device
+    pub null_output: bool,
+    /// Activity of output limiter, if enabled
+    pub limiter: Option<LimiterStatus>,
+    /// Requests queued on the default layer in the order they are going to be played
+    pub queue: Vec<QueuedSoundStatus>,
+    /// State of every layer, default layer goes first
+    pub layers: Vec<LayerStatus>,
+}
+
+/// Activity of output limiter since the system started, see [`SoundSystemStatus`] and
+/// [`OrbSoundSystemBuilder::limiter()`].
+///
+/// [`OrbSoundSystemBuilder::limiter()`]: crate::system::OrbSoundSystemBuilder::limiter
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LimiterStatus {
+    /// How many times the limiter started lowering gain
+    pub engaged: u64,
+    /// Total time the gain was lowered
+    pub limited: Duration,
+    /// Number of samples which got past the limiter and were soft clipped
+    pub clipped: u64,
+}
+
+/// State of a layer, see [`SoundSystemStatus`].
+#[derive(Debug, Clone)]
+pub struct LayerStatus {
+    /// Layer name
+    pub name: Arc<str>,
+    /// Volume of the layer, applied on top of the volume of the whole output
+    pub volume: f32,
+    /// Whether playback of the layer is paused
+    pub paused: bool,
+    /// Sound being played on the layer, if any
+    pub current: Option<PlayingSoundStatus>,
+    /// Requests queued on the layer in the order they are going to be played
+    pub queue: Vec<QueuedSoundStatus>,
+}
+
+/// State of currently playing sound, see [`SoundSystemStatus`].
+#[derive(Debug, Clone)]
+pub struct PlayingSoundStatus {
+    /// Identifier of the request
+    pub id: PlaybackId,
+    /// Sound being played
+    pub source: SoundSource,
+    /// Sound priority
+    pub priority: SoundPriority,
+    /// Playback time of the sound so far, not counting pauses
+    pub elapsed: Duration,
+}
+
+/// State of queued request, see [`SoundSystemStatus`].
+#[derive(Debug, Clone)]
+pub struct QueuedSoundStatus {
+    /// Identifier of the request
+    pub id: PlaybackId,
+    /// Sound to be played
+    pub source: SoundSource,
+    /// Sound priority
+    pub priority: SoundPriority,
+    /// Time left until play deadline of the request, if it has one
+    pub remaining: Option<Duration>,
+}
+
+/// Output device event, see [`OrbSoundSystemHandle::device_events()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceEvent {
+    /// Output stream failed and playback stopped. Contains error description
+    Lost(String),
+    /// Output device was reopened and playback continues. Contains device name
+    Reopened(String),
+    /// Output could not be written, e.g. rendered WAV file. Contains error description
+    WriteFailed(String),
+}
+
+/// Where sound file data comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SoundSource {
+    /// Path to file in filesystem
+    File(String),
+    /// Sound file data kept in memory
+    Bytes(Arc<[u8]>),
+    /// Sound file data embedded into the binary
+    StaticBytes(&'static [u8]),
+    /// Sound decoded into memory by [`SoundBank`]
+    Preloaded(PreloadedSound),
+}
+
+impl fmt::Display for SoundSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SoundSource::File(path) => write!(f, "{}", path),
+            SoundSource::Bytes(data) => write!(f, "<{} bytes in memory>", data.len()),
+            SoundSource::StaticBytes(data) => write!(f, "<{} bytes in memory>", data.len()),
+            SoundSource::Preloaded(sound) => write!(f, "<preloaded {}>", sound.name()),
+        }
+    }
 }
 
 /// Associated struct for [`SoundCommand::PlaySound`] command.
 #[derive(Debug)]
 pub(crate) struct PlaySoundCommand {
-    /// Path to file in filesystem
-    pub path: String,
+    /// Identifier of the request
+    pub id: PlaybackId,
+    /// Sound file data
+    pub source: SoundSource,
     /// Sound priority
     pub priority: SoundPriority,
     /// Deadline after which sound will not be played
     pub play_deadline: Option<Instant>,
+    /// Layer the sound is played on
+    pub layer: Arc<str>,
+    /// Linear gain applied to samples of the sound
+    pub gain: f32,
+    /// How many times the sound is played
+    pub repeat: Repeat,
+    /// Silence between repeats of the sound
+    pub repeat_gap: Duration,
+    /// Sender part of [`Playback::events`] channel
+    pub event_sender: EventSender<PlaybackEvent>,
+}
+
+impl PlaySoundCommand {
+    /// Notify requester about lifecycle event. Requester may not be interested in events and drop
+    /// the receiver, thus errors are ignored.
+    pub fn notify(&self, event: PlaybackEvent) {
+        let _ = self.event_sender.try_send(event);
+    }
+
+    /// Returns true if play deadline of the sound has passed by `now`.
+    pub fn is_expired(&self, now: Instant) -> bool {
+        matches!(self.play_deadline, Some(deadline) if now > deadline)
+    }
+}
+
+#[cfg(test)]
+impl PlaySoundCommand {
+    /// Creates command nobody listens events of.
+    pub(crate) fn mock(
+        path: &str,
+        priority: SoundPriority,
+        play_deadline: Option<Instant>,
+    ) -> Self {
+        Self {
+            id: PlaybackId(0),
+            source: SoundSource::File(path.to_string()),
+            priority,
+            play_deadline,
+            layer: DEFAULT_LAYER.into(),
+            gain: 1.0,
+            repeat: Repeat::Once,
+            repeat_gap: Duration::ZERO,
+            event_sender: mpsc::channel().0.into(),
+        }
+    }
+}
+
+/// How many times a sound is played, see [`OrbSoundSystemHandle::with_repeat()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Repeat {
+    /// Sound is played once
+    #[default]
+    Once,
+    /// Sound is played given number of times. It is played at least once, so `Times(0)` and
+    /// `Times(1)` both mean the same as [`Repeat::Once`]
+    Times(u32),
+    /// Sound is played until it is cancelled
+    Forever,
 }
 
 /// Sound priority. Used to determine what sound should be played next.
@@ -131,18 +833,28 @@ impl PartialEq<Self> for PlaySoundCommand {
 impl Eq for PlaySoundCommand {}
 
 #[cfg(test)]
+// Some of the tests decode WAV files
+#[cfg_attr(not(feature = "wav"), allow(unused_imports, dead_code))]
 mod test {
+    use std::sync::Arc;
     use std::time::{Duration, Instant};
 
-    use crate::handle::{OrbSoundSystemHandle, PlaySoundCommand, SoundCommand, SoundPriority};
+    use crate::bank::SoundBank;
+    use crate::clock::SystemClock;
+    use crate::handle::{
+        OrbSoundSystemHandle, PlaySoundCommand, PlaybackEvent, PlaybackOutcome, Repeat,
+        SoundCommand, SoundPriority, SoundSource, DEFAULT_LAYER,
+    };
+    use crate::OrbSoundSystemError;
 
     #[test]
+    #[cfg(feature = "wav")]
     fn test_handle() {
         let (tx, rx) = std::sync::mpsc::channel::<SoundCommand>();
-        let mut handle = OrbSoundSystemHandle { command_sender: tx };
-        handle
+        let mut handle = OrbSoundSystemHandle::new(tx, Arc::new(SystemClock));
+        let playback = handle
             .play_sound(
-                String::new().as_str(),
+                "sounds/test.wav",
                 SoundPriority::High,
                 Some(Duration::from_secs(1)),
             )
@@ -150,59 +862,282 @@ mod test {
         if let SoundCommand::PlaySound(command) = rx.recv().unwrap() {
             assert_eq!(
                 command,
-                PlaySoundCommand {
-                    path: String::new(),
-                    priority: SoundPriority::High,
-                    play_deadline: command.play_deadline.clone(),
-                }
+                PlaySoundCommand::mock(
+                    "sounds/test.wav",
+                    SoundPriority::High,
+                    command.play_deadline
+                )
             );
+            assert_eq!(command.id, playback.id);
+            command.notify(PlaybackEvent::Started);
+            assert!(matches!(
+                playback.events.try_recv(),
+                Ok(PlaybackEvent::Started)
+            ));
+        } else {
+            panic!()
+        }
+        // every request gets its own id, clones of the handle included
+        let other = handle
+            .clone()
+            .play_sound("sounds/test.wav", SoundPriority::High, None)
+            .unwrap();
+        assert_ne!(other.id, playback.id);
+        if let SoundCommand::PlaySound(command) = rx.recv().unwrap() {
+            assert_eq!(command.id, other.id);
         } else {
             panic!()
         }
 
         handle.set_volume(2.0).unwrap();
         assert_eq!(rx.recv().unwrap(), SoundCommand::SetVolume(2.0));
+        handle.set_volume_db(-6.0).unwrap();
+        assert_eq!(rx.recv().unwrap(), SoundCommand::SetVolumeDb(-6.0));
+        handle.adjust_volume_db(-0.5).unwrap();
+        assert_eq!(rx.recv().unwrap(), SoundCommand::AdjustVolumeDb(-0.5));
+        #[allow(deprecated)]
         handle.adjust_volume(-0.5).unwrap();
         assert_eq!(rx.recv().unwrap(), SoundCommand::AdjustVolume(-0.5));
+        handle.mute().unwrap();
+        assert_eq!(rx.recv().unwrap(), SoundCommand::Mute);
+        handle.unmute().unwrap();
+        assert_eq!(rx.recv().unwrap(), SoundCommand::Unmute);
         handle.pause().unwrap();
         assert_eq!(rx.recv().unwrap(), SoundCommand::Pause);
         handle.resume().unwrap();
         assert_eq!(rx.recv().unwrap(), SoundCommand::Resume);
+        handle.cancel(playback.id).unwrap();
+        assert_eq!(rx.recv().unwrap(), SoundCommand::Cancel(playback.id));
+        handle.cancel_path("path").unwrap();
+        assert_eq!(
+            rx.recv().unwrap(),
+            SoundCommand::CancelPath("path".to_string())
+        );
+        handle.cancel_priority(SoundPriority::High).unwrap();
+        assert_eq!(
+            rx.recv().unwrap(),
+            SoundCommand::CancelPriority(DEFAULT_LAYER.into(), SoundPriority::High)
+        );
+        handle.clear_queue().unwrap();
+        assert_eq!(
+            rx.recv().unwrap(),
+            SoundCommand::ClearQueue(DEFAULT_LAYER.into())
+        );
+        handle.stop().unwrap();
+        assert_eq!(rx.recv().unwrap(), SoundCommand::Stop(DEFAULT_LAYER.into()));
+        let _events = handle.device_events().unwrap();
+        assert!(matches!(
+            rx.recv().unwrap(),
+            SoundCommand::SubscribeDeviceEvents(_)
+        ));
+        let _events = handle.subscribe().unwrap();
+        assert!(matches!(rx.recv().unwrap(), SoundCommand::Subscribe(_)));
+        // nobody replies to status request
+        drop(rx);
+        assert!(matches!(
+            handle.status(),
+            Err(OrbSoundSystemError::SystemIsDown)
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "wav")]
+    fn play_and_wait() {
+        let (tx, rx) = std::sync::mpsc::channel::<SoundCommand>();
+        let mut handle = OrbSoundSystemHandle::new(tx, Arc::new(SystemClock));
+        let system = std::thread::spawn(move || {
+            if let SoundCommand::PlaySound(command) = rx.recv().unwrap() {
+                command.notify(PlaybackEvent::Queued);
+                command.notify(PlaybackEvent::Started);
+                command.notify(PlaybackEvent::Finished);
+            }
+            // nobody notifies the second request, it gets cancelled on timeout
+            if let SoundCommand::PlaySound(command) = rx.recv().unwrap() {
+                assert_eq!(rx.recv().unwrap(), SoundCommand::Cancel(command.id));
+            } else {
+                panic!()
+            }
+        });
+        let outcome = handle
+            .play_and_wait("sounds/test.wav", SoundPriority::High, None, None)
+            .unwrap();
+        assert!(matches!(outcome, PlaybackOutcome::Finished));
+        let outcome = handle
+            .play_and_wait(
+                "sounds/test.wav",
+                SoundPriority::High,
+                None,
+                Some(Duration::from_millis(10)),
+            )
+            .unwrap();
+        assert!(matches!(outcome, PlaybackOutcome::TimedOut));
+        system.join().unwrap();
+
+        let result = handle.play_and_wait("sounds/test.wav", SoundPriority::High, None, None);
+        assert!(matches!(result, Err(OrbSoundSystemError::SystemIsDown)));
+    }
+
+    #[test]
+    #[cfg(feature = "wav")]
+    fn layer() {
+        let (tx, rx) = std::sync::mpsc::channel::<SoundCommand>();
+        let mut handle = OrbSoundSystemHandle::new(tx, Arc::new(SystemClock))
+            .with_layers(vec![DEFAULT_LAYER.into(), "ambient".into()]);
+        assert_eq!(handle.layer_name(), DEFAULT_LAYER);
+        assert!(matches!(
+            handle.layer("missing"),
+            Err(OrbSoundSystemError::LayerNotFound(name)) if name == "missing"
+        ));
+
+        let mut ambient = handle.layer("ambient").unwrap();
+        assert_eq!(ambient.layer_name(), "ambient");
+        ambient
+            .play_sound("sounds/test.wav", SoundPriority::Default, None)
+            .unwrap();
+        if let SoundCommand::PlaySound(command) = rx.recv().unwrap() {
+            assert_eq!(&*command.layer, "ambient");
+        } else {
+            panic!()
+        }
+        // gain is kept along with the layer
+        ambient
+            .with_gain(0.5)
+            .play_sound("sounds/test.wav", SoundPriority::Default, None)
+            .unwrap();
+        if let SoundCommand::PlaySound(command) = rx.recv().unwrap() {
+            assert_eq!((&*command.layer, command.gain), ("ambient", 0.5));
+        } else {
+            panic!()
+        }
+        ambient
+            .with_repeat(Repeat::Times(3), Duration::from_millis(200))
+            .play_sound("sounds/test.wav", SoundPriority::Default, None)
+            .unwrap();
+        if let SoundCommand::PlaySound(command) = rx.recv().unwrap() {
+            assert_eq!(command.repeat, Repeat::Times(3));
+            assert_eq!(command.repeat_gap, Duration::from_millis(200));
+        } else {
+            panic!()
+        }
+        ambient.set_layer_volume(0.5).unwrap();
+        assert_eq!(
+            rx.recv().unwrap(),
+            SoundCommand::SetLayerVolume("ambient".into(), 0.5)
+        );
+        ambient.pause_layer().unwrap();
+        assert_eq!(
+            rx.recv().unwrap(),
+            SoundCommand::PauseLayer("ambient".into())
+        );
+        ambient.resume_layer().unwrap();
+        assert_eq!(
+            rx.recv().unwrap(),
+            SoundCommand::ResumeLayer("ambient".into())
+        );
+        ambient.stop().unwrap();
+        assert_eq!(rx.recv().unwrap(), SoundCommand::Stop("ambient".into()));
+        // layer of the original handle is not changed
+        handle.stop().unwrap();
+        assert_eq!(rx.recv().unwrap(), SoundCommand::Stop(DEFAULT_LAYER.into()));
+    }
+
+    #[test]
+    #[cfg(feature = "wav")]
+    fn play_bytes() {
+        let (tx, rx) = std::sync::mpsc::channel::<SoundCommand>();
+        let mut handle = OrbSoundSystemHandle::new(tx, Arc::new(SystemClock));
+        let data = std::fs::read("sounds/test.wav").unwrap();
+        handle
+            .play_bytes(data.as_slice(), SoundPriority::High, None)
+            .unwrap();
+        if let SoundCommand::PlaySound(command) = rx.recv().unwrap() {
+            assert_eq!(command.source, SoundSource::Bytes(data.into()));
+        } else {
+            panic!()
+        }
+        let result = handle.play_bytes(&b"not a sound"[..], SoundPriority::High, None);
+        assert!(matches!(result, Err(OrbSoundSystemError::SoundFileErr(_))));
     }
 
     #[test]
+    #[cfg(feature = "wav")]
+    fn play_static_bytes() {
+        static DATA: &[u8] = include_bytes!("../sounds/test.wav");
+        let (tx, rx) = std::sync::mpsc::channel::<SoundCommand>();
+        let mut handle = OrbSoundSystemHandle::new(tx, Arc::new(SystemClock));
+        handle
+            .play_static_bytes(
+                include_bytes!("../sounds/test.wav"),
+                SoundPriority::High,
+                None,
+            )
+            .unwrap();
+        if let SoundCommand::PlaySound(command) = rx.recv().unwrap() {
+            assert!(matches!(command.source, SoundSource::StaticBytes(data) if data == DATA));
+        } else {
+            panic!()
+        }
+        let result = handle.play_static_bytes(b"not a sound", SoundPriority::High, None);
+        assert!(matches!(result, Err(OrbSoundSystemError::SoundFileErr(_))));
+    }
+
+    #[test]
+    #[cfg(feature = "wav")]
+    fn play_named() {
+        let (tx, rx) = std::sync::mpsc::channel::<SoundCommand>();
+        let mut handle = OrbSoundSystemHandle::new(tx, Arc::new(SystemClock));
+        let result = handle.play_named("test", SoundPriority::High, None);
+        assert!(matches!(result, Err(OrbSoundSystemError::SoundBankErr(_))));
+
+        let bank = SoundBank::new(usize::MAX);
+        bank.load("test", &SoundSource::File("sounds/test.wav".to_string()))
+            .unwrap();
+        // registered bank is shared with clones
+        handle.clone().register_sound_bank(bank);
+        handle
+            .play_named("test", SoundPriority::High, None)
+            .unwrap();
+        if let SoundCommand::PlaySound(command) = rx.recv().unwrap() {
+            assert!(matches!(command.source, SoundSource::Preloaded(_)));
+        } else {
+            panic!()
+        }
+        let result = handle.play_named("missing", SoundPriority::High, None);
+        assert!(matches!(result, Err(OrbSoundSystemError::SoundBankErr(_))));
+    }
+
+    #[test]
+    fn invalid_file() {
+        let (tx, rx) = std::sync::mpsc::channel::<SoundCommand>();
+        let mut handle = OrbSoundSystemHandle::new(tx, Arc::new(SystemClock));
+        let result = handle.play_sound("sounds/missing.wav", SoundPriority::High, None);
+        assert!(matches!(result, Err(OrbSoundSystemError::SoundFileErr(_))));
+        // invalid files never reach the queue
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    #[allow(clippy::vec_init_then_push, clippy::get_first)]
     fn sound_priority_sorting() {
         let mut queue = Vec::new();
-        queue.push(PlaySoundCommand {
-            path: String::new(),
-            priority: SoundPriority::Default,
-            play_deadline: None,
-        });
-        queue.push(PlaySoundCommand {
-            path: String::new(),
-            priority: SoundPriority::Default,
-            play_deadline: Some(Instant::now() + Duration::from_secs(2)),
-        });
-        queue.push(PlaySoundCommand {
-            path: String::new(),
-            priority: SoundPriority::High,
-            play_deadline: None,
-        });
-        queue.push(PlaySoundCommand {
-            path: String::new(),
-            priority: SoundPriority::High,
-            play_deadline: Some(Instant::now() + Duration::from_secs(5)),
-        });
-        queue.push(PlaySoundCommand {
-            path: String::new(),
-            priority: SoundPriority::High,
-            play_deadline: Some(Instant::now() + Duration::from_secs(3)),
-        });
-        queue.push(PlaySoundCommand {
-            path: String::new(),
-            priority: SoundPriority::Urgent,
-            play_deadline: None,
-        });
+        queue.push(PlaySoundCommand::mock("", SoundPriority::Default, None));
+        queue.push(PlaySoundCommand::mock(
+            "",
+            SoundPriority::Default,
+            Some(Instant::now() + Duration::from_secs(2)),
+        ));
+        queue.push(PlaySoundCommand::mock("", SoundPriority::High, None));
+        queue.push(PlaySoundCommand::mock(
+            "",
+            SoundPriority::High,
+            Some(Instant::now() + Duration::from_secs(5)),
+        ));
+        queue.push(PlaySoundCommand::mock(
+            "",
+            SoundPriority::High,
+            Some(Instant::now() + Duration::from_secs(3)),
+        ));
+        queue.push(PlaySoundCommand::mock("", SoundPriority::Urgent, None));
         queue.sort();
 
         assert_eq!(queue.get(0).unwrap().priority, SoundPriority::Urgent);
@@ -2,9 +2,32 @@
 //!
 //! Provides possibility to;
 //!
-//! - Play WAV files from file system
-//! - Control volume by setting exact value or adjusting by given amount
+//! - Play WAV, MP3, FLAC and Ogg Vorbis files from file system. Each format is enabled by cargo
+//!   feature of the same name (`wav`, `mp3`, `flac` and `vorbis`), all of them are enabled by
+//!   default
+//! - Play sounds kept in memory, e.g. embedded into the binary with `include_bytes!`
+//! - Preload frequently played sounds into [`bank::SoundBank`] to skip file access and decoding
+//! - Select output device by name, with an ordered list of fallback devices
+//! - Recover from output device loss by reopening the device
+//! - Render output into WAV file or memory instead of playing it, e.g. for tests without hardware
+//! - Discard output at real time pace on machines without sound card
+//! - Play sounds on separate layers mixed together, e.g. background music under short UI sounds.
+//!   Each layer has its own queue, volume and pause state
+//! - Duck lower layers automatically while important sounds play
+//! - Fade sounds in and out, click-free pause, resume and volume changes
+//! - Control volume in decibels by setting exact value or adjusting by given amount, within
+//!   configurable limits, and mute it
+//! - Keep volume, mute state and layer settings across restarts
+//! - Adjust gain of every request and normalize loudness of preloaded sounds
+//! - Protect the speaker with output limiter
+//! - Repeat sounds given number of times or until cancelled, with silence between repeats
 //! - Pause/Resume playback
+//! - Replace the clock used for scheduling, e.g. to test play deadlines without waiting
+//! - Observe all sound activity through a broadcast event stream
+//! - Query what is playing, volume, pause state and queued requests
+//! - Track playback requests: get notified when sound started, finished or was dropped, or block
+//!   until it is over
+//! - Await playback and events from async code, enabled by `async` cargo feature
 //!
 //! Under the hood it runs event loop on a separate thread and uses ring buffer to eliminate buffer
 //! under-run conditions. Basic usage:
@@ -27,9 +50,15 @@
 use rodio::{DevicesError, PlayError, StreamError};
 use thiserror_impl::Error;
 
+#[cfg(feature = "async")]
+pub use async_handle::AsyncOrbSoundSystemHandle;
 pub use handle::OrbSoundSystemHandle;
 pub use system::OrbSoundSystem;
 
+#[cfg(feature = "async")]
+pub mod async_handle;
+pub mod bank;
+pub mod clock;
 pub mod handle;
 pub mod system;
 
@@ -37,12 +66,20 @@ pub mod system;
 pub enum OrbSoundSystemError {
     #[error("Sound device error")]
     DeviceErr(DevicesError),
+    #[error("Sound device not found")]
+    DeviceNotFound(String),
     #[error("Sound stream error")]
     StreamErr(StreamError),
     #[error("Playback error")]
     PlayErr(PlayError),
     #[error("Sound file error")]
     SoundFileErr(String),
+    #[error("Sound bank error")]
+    SoundBankErr(String),
+    #[error("Layer not found")]
+    LayerNotFound(String),
+    #[error("Output error")]
+    OutputErr(String),
     #[error("System is down")]
     SystemIsDown,
 }
@@ -0,0 +1,247 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+use crate::clock::{Clock, SystemClock};
+use crate::handle::{OrbSoundSystemHandle, SoundCommand, DEFAULT_LAYER};
+use crate::system::{
+    DuckingRule, Fades, LimiterSettings, OrbSoundSystem, OutputBackend, PreemptionPolicy,
+    VolumeLimits, DEFAULT_MIX_CHANNELS, DEFAULT_MIX_SAMPLE_RATE,
+};
+use crate::OrbSoundSystemError;
+
+/// Builder used to configure [`OrbSoundSystem`] before running it. Created by
+/// [`OrbSoundSystem::builder()`].
+pub struct OrbSoundSystemBuilder {
+    pub(crate) preemption: PreemptionPolicy,
+    pub(crate) device: Option<String>,
+    pub(crate) fallback_devices: Vec<String>,
+    pub(crate) output: Option<Box<dyn OutputBackend + Send>>,
+    pub(crate) require_device: bool,
+    pub(crate) clock: Arc<dyn Clock>,
+    pub(crate) layers: Vec<String>,
+    pub(crate) mix_format: (u16, u32),
+    pub(crate) ducking: Vec<DuckingRule>,
+    pub(crate) fades: Fades,
+    pub(crate) volume_limits: VolumeLimits,
+    pub(crate) state_file: Option<PathBuf>,
+    pub(crate) loudness_target: Option<f32>,
+    pub(crate) limiter: Option<LimiterSettings>,
+}
+
+impl Default for OrbSoundSystemBuilder {
+    fn default() -> Self {
+        Self {
+            preemption: PreemptionPolicy::default(),
+            device: None,
+            fallback_devices: Vec::new(),
+            output: None,
+            require_device: false,
+            clock: Arc::new(SystemClock),
+            layers: Vec::new(),
+            mix_format: (DEFAULT_MIX_CHANNELS, DEFAULT_MIX_SAMPLE_RATE),
+            ducking: Vec::new(),
+            fades: Fades::default(),
+            volume_limits: VolumeLimits::default(),
+            state_file: None,
+            loudness_target: None,
+            limiter: None,
+        }
+    }
+}
+
+impl fmt::Debug for OrbSoundSystemBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OrbSoundSystemBuilder")
+            .field("preemption", &self.preemption)
+            .field("device", &self.device)
+            .field("fallback_devices", &self.fallback_devices)
+            .field("output", &self.output.as_ref().map(|_| "OutputBackend"))
+            .field("require_device", &self.require_device)
+            .field("clock", &self.clock)
+            .field("layers", &self.layers)
+            .field("mix_format", &self.mix_format)
+            .field("ducking", &self.ducking)
+            .field("fades", &self.fades)
+            .field("volume_limits", &self.volume_limits)
+            .field("state_file", &self.state_file)
+            .field("loudness_target", &self.loudness_target)
+            .field("limiter", &self.limiter)
+            .finish()
+    }
+}
+
+impl OrbSoundSystemBuilder {
+    /// Set policy applied when urgent sound is requested while less important one is playing. By
+    /// default urgent sounds never interrupt playing sounds.
+    pub fn preemption(mut self, policy: PreemptionPolicy) -> Self {
+        self.preemption = policy;
+        self
+    }
+
+    /// Use output device with given name, see [`OrbSoundSystem::list_output_devices()`]. By
+    /// default the system uses default output device of the host.
+    pub fn device(mut self, name: impl Into<String>) -> Self {
+        self.device = Some(name.into());
+        self
+    }
+
+    /// Output devices tried in given order when the device set with
+    /// [`OrbSoundSystemBuilder::device()`] can not be opened. Default device is not tried in that
+    /// case. If none of the devices works, output goes to [`NullOutput`], which is reported by
+    /// [`SoundSystemStatus::null_output`], unless [`OrbSoundSystemBuilder::require_device()`] is
+    /// set.
+    ///
+    /// [`NullOutput`]: crate::system::NullOutput
+    /// [`SoundSystemStatus::null_output`]: crate::handle::SoundSystemStatus::null_output
+    pub fn fallback_devices<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.fallback_devices = names.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Send output to given backend instead of sound device, e.g. [`RenderOutput`] to render it
+    /// into WAV file. Devices set with [`OrbSoundSystemBuilder::device()`] are ignored in that
+    /// case.
+    ///
+    /// [`RenderOutput`]: crate::system::RenderOutput
+    pub fn output(mut self, backend: impl OutputBackend + Send + 'static) -> Self {
+        self.output = Some(Box::new(backend));
+        self
+    }
+
+    /// Fail initialization if none of the selected sound devices works. By default [`NullOutput`]
+    /// is used in that case, so the system runs on machines without sound card, like simulators.
+    ///
+    /// [`NullOutput`]: crate::system::NullOutput
+    pub fn require_device(mut self) -> Self {
+        self.require_device = true;
+        self
+    }
+
+    /// Use given clock to schedule sounds instead of real time, e.g. [`ManualClock`] in tests. The
+    /// clock is shared by the system and its handles.
+    ///
+    /// [`ManualClock`]: crate::clock::ManualClock
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Add layer with given `name`. Sounds played on different layers are mixed together, see
+    /// [`OrbSoundSystemHandle::layer()`]. Layer [`DEFAULT_LAYER`] always exists.
+    pub fn layer(mut self, name: impl Into<String>) -> Self {
+        self.layers.push(name.into());
+        self
+    }
+
+    /// Add rule lowering volume of some layers while certain sounds play on other layers. By
+    /// default layers are never ducked.
+    pub fn ducking(mut self, rule: DuckingRule) -> Self {
+        self.ducking.push(rule);
+        self
+    }
+
+    /// Set durations of fades applied when sounds start and stop, output is paused and resumed
+    /// or its volume changes. By default all changes take effect immediately.
+    pub fn fades(mut self, fades: Fades) -> Self {
+        self.fades = fades;
+        self
+    }
+
+    /// Set range of volume of the whole output. By default volume goes from -60dB, which is
+    /// silence, up to 6dB, while adjusting does not raise it above 0dB.
+    pub fn volume_limits(mut self, limits: VolumeLimits) -> Self {
+        self.volume_limits = limits;
+        self
+    }
+
+    /// Keep volume, mute state and settings of layers in file at given `path`, so they survive
+    /// restarts. The file is read when the system starts and rewritten a second after they change,
+    /// so a burst of changes is written once. Pending changes are written on shutdown. By default
+    /// nothing is kept.
+    pub fn state_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.state_file = Some(path.into());
+        self
+    }
+
+    /// Scale sounds preloaded into [`SoundBank`] so that their loudness, measured as RMS level when
+    /// they are loaded, is equal to `target_db` decibels relative to full scale, e.g. -20dB. Sounds
+    /// played from files or bytes are not normalized. Quiet sounds are boosted by at most 12dB and
+    /// sounds below -70dB are considered silent and left as they are. By default sounds play as
+    /// they are recorded.
+    ///
+    /// [`SoundBank`]: crate::bank::SoundBank
+    pub fn normalize_loudness(mut self, target_db: f32) -> Self {
+        self.loudness_target = Some(target_db);
+        self
+    }
+
+    /// Protect the speaker by passing mixed output through look-ahead peak limiter and soft
+    /// clipper, which keep it below ceiling of the `settings` whatever the volume is. Activity of
+    /// the limiter is reported by [`OrbSoundSystemHandle::status()`]. By default output is not
+    /// limited.
+    pub fn limiter(mut self, settings: LimiterSettings) -> Self {
+        self.limiter = Some(settings);
+        self
+    }
+
+    /// Mix layers into samples with given number of channels and sample rate. Sounds of other
+    /// formats are converted. Output backend gets samples of this format, so matching it with the
+    /// format of sound device saves another conversion. Stereo 44.1kHz is used by default.
+    pub fn mix_format(mut self, channels: u16, sample_rate: u32) -> Self {
+        self.mix_format = (channels.max(1), sample_rate.max(1));
+        self
+    }
+
+    /// Names of all layers, default layer goes first.
+    pub(crate) fn layers(&self) -> Vec<Arc<str>> {
+        let mut layers: Vec<Arc<str>> = vec![DEFAULT_LAYER.into()];
+        for name in &self.layers {
+            if !layers.iter().any(|layer| **layer == **name) {
+                layers.push(name.as_str().into());
+            }
+        }
+        layers
+    }
+
+    /// Names of selected output devices in order of preference.
+    pub(crate) fn devices(&self) -> Vec<String> {
+        self.device
+            .iter()
+            .chain(&self.fallback_devices)
+            .cloned()
+            .collect()
+    }
+
+    /// Initialize and run Orb's sound system. Spawns a thread and runs event loop on it. Returns
+    /// either [`OrbSoundSystemHandle`] or some sort of initialization error.
+    pub fn run(self) -> Result<OrbSoundSystemHandle, OrbSoundSystemError> {
+        let (command_sender, command_receiver) = mpsc::channel::<SoundCommand>();
+        let (err_sender, err_receiver) = mpsc::channel::<Option<OrbSoundSystemError>>();
+        let handle = OrbSoundSystemHandle::new(command_sender, self.clock.clone())
+            .with_layers(self.layers());
+        let waker = Arc::downgrade(&handle.command_sender);
+
+        thread::spawn(
+            move || match OrbSoundSystem::init(command_receiver, waker, self) {
+                Ok(system) => {
+                    err_sender.send(None).unwrap();
+                    system.run_event_loop();
+                }
+                Err(e) => {
+                    err_sender.send(Some(e)).unwrap();
+                }
+            },
+        );
+
+        match err_receiver.recv().unwrap() {
+            Some(err) => Err(err),
+            None => Ok(handle),
+        }
+    }
+}
@@ -0,0 +1,320 @@
+//! Output of the sink to a sound device. The stream is built directly on cpal instead of rodio's
+//! `OutputStream`, so that stream errors reach the event loop and the device can be reopened
+//! without losing the sink and sounds queued in it.
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Weak;
+use std::time::{Duration, Instant};
+
+use rodio::cpal::traits::{HostTrait, StreamTrait};
+use rodio::cpal::{self, Device, Sample, SampleFormat, Stream};
+use rodio::source::UniformSourceIterator;
+use rodio::{DeviceTrait, Source, StreamError};
+
+use crate::handle::{DeviceEvent, SoundCommand};
+use crate::system::output::{OutputBackend, SinkOutput};
+use crate::OrbSoundSystemError;
+
+// Delay before the first attempt to reopen lost device, doubled after each failed attempt
+const INITIAL_REOPEN_DELAY: Duration = Duration::from_millis(100);
+const MAX_REOPEN_DELAY: Duration = Duration::from_secs(5);
+
+/// Returns names of all output devices available on the default host.
+pub(crate) fn list_output_devices() -> Result<Vec<String>, OrbSoundSystemError> {
+    let devices = cpal::default_host()
+        .output_devices()
+        .map_err(OrbSoundSystemError::DeviceErr)?;
+    // Devices which name can not be retrieved can not be selected anyway
+    Ok(devices.filter_map(|device| device.name().ok()).collect())
+}
+
+/// Output backend playing samples of the sink on a sound device. Reopens the device with backoff
+/// when its stream fails.
+pub(crate) struct DeviceOutput {
+    /// Names of devices in order of preference, default device is used if empty
+    device_names: Vec<String>,
+    /// Output of the sink while no stream owns it
+    source: Option<SinkOutput>,
+    /// Sink output given back by dropped streams
+    returned_source: Receiver<SinkOutput>,
+    return_sender: Sender<SinkOutput>,
+    /// Missing while device is lost
+    stream: Option<Stream>,
+    error_sender: Sender<cpal::StreamError>,
+    errors: Receiver<cpal::StreamError>,
+    /// Command channel of the system, used to wake event loop up once the stream fails
+    waker: Weak<Sender<SoundCommand>>,
+    /// Time of the next attempt to reopen lost device
+    reopen_at: Option<Instant>,
+    reopen_delay: Duration,
+}
+
+impl DeviceOutput {
+    /// Create output using the first of given devices that works. Falls back to the default device
+    /// if no device names are given.
+    pub(crate) fn new(device_names: Vec<String>, waker: Weak<Sender<SoundCommand>>) -> Self {
+        let (error_sender, errors) = mpsc::channel();
+        let (return_sender, returned_source) = mpsc::channel();
+        Self {
+            device_names,
+            source: None,
+            returned_source,
+            return_sender,
+            stream: None,
+            error_sender,
+            errors,
+            waker,
+            reopen_at: None,
+            reopen_delay: INITIAL_REOPEN_DELAY,
+        }
+    }
+
+    /// Open stream on the first device that works. Returns name of the device or error of the
+    /// last device tried if none of them works.
+    fn open_stream(&mut self) -> Result<String, OrbSoundSystemError> {
+        // Drop errors reported by the failed stream
+        while self.errors.try_recv().is_ok() {}
+
+        if self.device_names.is_empty() {
+            let device = cpal::default_host()
+                .default_output_device()
+                .ok_or(OrbSoundSystemError::StreamErr(StreamError::NoDevice))?;
+            self.stream = Some(self.build_stream(&device)?);
+            return Ok(device.name().unwrap_or_default());
+        }
+        let mut last_error = None;
+        for name in self.device_names.clone() {
+            match find_device(&name).and_then(|device| self.build_stream(&device)) {
+                Ok(stream) => {
+                    self.stream = Some(stream);
+                    return Ok(name);
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap())
+    }
+
+    fn build_stream(&mut self, device: &Device) -> Result<Stream, OrbSoundSystemError> {
+        let config = device
+            .default_output_config()
+            .map_err(|e| OrbSoundSystemError::StreamErr(e.into()))?;
+        // Stream which failed to build or has been dropped gives the output back
+        while let Ok(output) = self.returned_source.try_recv() {
+            self.source = Some(output);
+        }
+        let output = self.source.take().expect("output is not started");
+        let source = StreamSource {
+            output: Some(output),
+            return_sender: self.return_sender.clone(),
+        };
+        let mut samples =
+            UniformSourceIterator::<_, f32>::new(source, config.channels(), config.sample_rate().0);
+        let error_sender = self.error_sender.clone();
+        let waker = self.waker.clone();
+        let error_callback = move |err| {
+            let _ = error_sender.send(err);
+            // Event loop may be sleeping until the next command, so the loss would go unnoticed
+            if let Some(commands) = waker.upgrade() {
+                let _ = commands.send(SoundCommand::DeviceError);
+            }
+        };
+        let stream = match config.sample_format() {
+            SampleFormat::F32 => device.build_output_stream::<f32, _, _>(
+                &config.config(),
+                move |data, _| {
+                    data.iter_mut()
+                        .for_each(|d| *d = samples.next().unwrap_or(0.0))
+                },
+                error_callback,
+            ),
+            SampleFormat::I16 => device.build_output_stream::<i16, _, _>(
+                &config.config(),
+                move |data, _| {
+                    data.iter_mut()
+                        .for_each(|d| *d = samples.next().map_or(0, |s| s.to_i16()))
+                },
+                error_callback,
+            ),
+            SampleFormat::U16 => device.build_output_stream::<u16, _, _>(
+                &config.config(),
+                move |data, _| {
+                    data.iter_mut()
+                        .for_each(|d| *d = samples.next().map_or(u16::MAX / 2, |s| s.to_u16()))
+                },
+                error_callback,
+            ),
+        }
+        .map_err(|e| OrbSoundSystemError::StreamErr(e.into()))?;
+        stream
+            .play()
+            .map_err(|e| OrbSoundSystemError::StreamErr(e.into()))?;
+        Ok(stream)
+    }
+}
+
+impl OutputBackend for DeviceOutput {
+    fn start(&mut self, output: SinkOutput) -> Result<(), OrbSoundSystemError> {
+        self.source = Some(output);
+        self.open_stream()?;
+        Ok(())
+    }
+
+    /// Check whether output stream has failed and try to reopen lost device once it is time to.
+    /// Sink keeps its sounds meanwhile, so playback continues where it stopped.
+    fn check(&mut self, now: Instant) -> Option<DeviceEvent> {
+        if self.stream.is_some() {
+            let err = self.errors.try_recv().ok()?;
+            self.stream = None;
+            self.reopen_at = Some(now + INITIAL_REOPEN_DELAY);
+            self.reopen_delay = INITIAL_REOPEN_DELAY;
+            return Some(DeviceEvent::Lost(err.to_string()));
+        }
+        if self.reopen_at? > now {
+            return None;
+        }
+        match self.open_stream() {
+            Ok(name) => {
+                self.reopen_at = None;
+                Some(DeviceEvent::Reopened(name))
+            }
+            Err(_) => {
+                self.reopen_delay = (self.reopen_delay * 2).min(MAX_REOPEN_DELAY);
+                self.reopen_at = Some(now + self.reopen_delay);
+                None
+            }
+        }
+    }
+
+    /// Returns time of the next attempt to reopen lost device.
+    fn next_check(&self) -> Option<Instant> {
+        self.reopen_at
+    }
+}
+
+fn find_device(name: &str) -> Result<Device, OrbSoundSystemError> {
+    cpal::default_host()
+        .output_devices()
+        .map_err(OrbSoundSystemError::DeviceErr)?
+        .find(|device| device.name().is_ok_and(|n| n == name))
+        .ok_or_else(|| OrbSoundSystemError::DeviceNotFound(name.to_string()))
+}
+
+/// Sink output owned by the stream pulling its samples, so audio callback never waits for a lock.
+/// Output is sent back to [`DeviceOutput`] once the stream is dropped, e.g. because it failed, and
+/// is handed over to the next stream.
+struct StreamSource {
+    /// Taken only when dropped
+    output: Option<SinkOutput>,
+    return_sender: Sender<SinkOutput>,
+}
+
+impl Iterator for StreamSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.output.as_mut().and_then(Iterator::next)
+    }
+}
+
+impl Source for StreamSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.output.as_ref().and_then(Source::current_frame_len)
+    }
+
+    fn channels(&self) -> u16 {
+        self.output.as_ref().map_or(1, Source::channels)
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.output.as_ref().map_or(1, Source::sample_rate)
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl Drop for StreamSource {
+    fn drop(&mut self) {
+        if let Some(output) = self.output.take() {
+            let _ = self.return_sender.send(output);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Weak;
+    use std::time::Instant;
+
+    use rodio::cpal;
+    use rodio::Sink;
+
+    use crate::handle::DeviceEvent;
+    use crate::system::device::{
+        list_output_devices, DeviceOutput, StreamSource, INITIAL_REOPEN_DELAY,
+    };
+    use crate::system::output::OutputBackend;
+    use crate::OrbSoundSystemError;
+
+    #[test]
+    fn return_source() {
+        let output = DeviceOutput::new(vec![], Weak::new());
+        let source = StreamSource {
+            output: Some(Sink::new_idle().1),
+            return_sender: output.return_sender.clone(),
+        };
+        // stream which fails or gets dropped gives sink output back
+        drop(source);
+        assert!(output.returned_source.try_recv().is_ok());
+    }
+
+    // Needs sound device, which CI machines do not have
+    #[test]
+    #[ignore]
+    fn open_with_fallback() {
+        let devices = list_output_devices().unwrap();
+        assert!(!devices.is_empty());
+        assert!(DeviceOutput::new(vec![], Weak::new())
+            .start(Sink::new_idle().1)
+            .is_ok());
+
+        let result =
+            DeviceOutput::new(vec!["missing".to_string()], Weak::new()).start(Sink::new_idle().1);
+        assert!(
+            matches!(result, Err(OrbSoundSystemError::DeviceNotFound(name)) if name == "missing")
+        );
+        // falls back to next device in the list
+        let fallback = vec!["missing".to_string(), devices[0].clone()];
+        assert!(DeviceOutput::new(fallback, Weak::new())
+            .start(Sink::new_idle().1)
+            .is_ok());
+    }
+
+    // Needs sound device, which CI machines do not have
+    #[test]
+    #[ignore]
+    fn reopen() {
+        let mut output = DeviceOutput::new(vec![], Weak::new());
+        output.start(Sink::new_idle().1).unwrap();
+        let now = Instant::now();
+        assert_eq!(output.check(now), None);
+
+        output
+            .error_sender
+            .send(cpal::StreamError::DeviceNotAvailable)
+            .unwrap();
+        assert!(matches!(output.check(now), Some(DeviceEvent::Lost(_))));
+        assert!(output.stream.is_none());
+        assert_eq!(output.next_check(), Some(now + INITIAL_REOPEN_DELAY));
+        assert_eq!(output.check(now), None);
+
+        let reopen_at = output.next_check().unwrap();
+        assert!(matches!(
+            output.check(reopen_at),
+            Some(DeviceEvent::Reopened(_))
+        ));
+        assert!(output.stream.is_some());
+        assert_eq!(output.next_check(), None);
+    }
+}
@@ -0,0 +1,102 @@
+//! Rules lowering volume of layers while important sounds play on other layers.
+use std::time::Duration;
+
+use crate::handle::SoundPriority;
+
+// Ramp times used by DuckingRule::new()
+const DEFAULT_ATTACK: Duration = Duration::from_millis(50);
+const DEFAULT_RELEASE: Duration = Duration::from_millis(300);
+
+/// Rule lowering volume of some layers while a sound matching the trigger plays on another layer,
+/// e.g. to dip background music under voice prompts. Volume is restored once such sounds are
+/// over. If several rules apply to a layer, the one ducking it most wins.
+///
+/// Ducking is applied when layers are mixed, on top of layer volumes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuckingRule {
+    /// What makes the rule apply
+    pub trigger: DuckingTrigger,
+    /// Names of layers which are ducked. Empty list means all layers except the one the
+    /// triggering sound plays on
+    pub ducked: Vec<String>,
+    /// How much volume of ducked layers is lowered, in decibels
+    pub amount_db: f32,
+    /// Time it takes to lower the volume
+    pub attack: Duration,
+    /// Time it takes to restore the volume
+    pub release: Duration,
+}
+
+impl DuckingRule {
+    /// Create rule ducking all other layers by `amount_db` decibels while a sound matching
+    /// `trigger` plays. Volume is lowered in 50ms and restored in 300ms.
+    pub fn new(trigger: DuckingTrigger, amount_db: f32) -> Self {
+        Self {
+            trigger,
+            ducked: Vec::new(),
+            amount_db,
+            attack: DEFAULT_ATTACK,
+            release: DEFAULT_RELEASE,
+        }
+    }
+
+    /// Duck only layers with given names.
+    pub fn layers<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.ducked = names.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Set times it takes to lower and to restore the volume.
+    pub fn ramp(mut self, attack: Duration, release: Duration) -> Self {
+        self.attack = attack;
+        self.release = release;
+        self
+    }
+
+    /// Returns true if a sound with given `priority` playing on layer `playing` makes the rule
+    /// duck layer `ducked`.
+    pub(crate) fn applies(&self, priority: &SoundPriority, playing: &str, ducked: &str) -> bool {
+        let triggered = match &self.trigger {
+            DuckingTrigger::Priority(min_priority) => priority <= min_priority,
+            DuckingTrigger::Layer(name) => name == playing,
+        };
+        triggered
+            && playing != ducked
+            && (self.ducked.is_empty() || self.ducked.iter().any(|name| name == ducked))
+    }
+}
+
+/// What makes [`DuckingRule`] apply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DuckingTrigger {
+    /// Sound of given priority or higher plays on any layer
+    Priority(SoundPriority),
+    /// Any sound plays on the layer with given name
+    Layer(String),
+}
+
+#[cfg(test)]
+mod test {
+    use crate::handle::SoundPriority;
+    use crate::system::ducking::{DuckingRule, DuckingTrigger};
+
+    #[test]
+    fn applies() {
+        let rule = DuckingRule::new(DuckingTrigger::Priority(SoundPriority::High), 12.0);
+        assert!(rule.applies(&SoundPriority::Urgent, "default", "ambient"));
+        assert!(rule.applies(&SoundPriority::High, "default", "ambient"));
+        assert!(!rule.applies(&SoundPriority::Default, "default", "ambient"));
+        // layer of the triggering sound is never ducked
+        assert!(!rule.applies(&SoundPriority::High, "ambient", "ambient"));
+
+        let rule =
+            DuckingRule::new(DuckingTrigger::Layer("voice".to_string()), 12.0).layers(["ambient"]);
+        assert!(rule.applies(&SoundPriority::Default, "voice", "ambient"));
+        assert!(!rule.applies(&SoundPriority::Default, "voice", "default"));
+        assert!(!rule.applies(&SoundPriority::Urgent, "default", "ambient"));
+    }
+}
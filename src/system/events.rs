@@ -0,0 +1,140 @@
+//! Delivery of playback events: requesters of sounds learn about their outcome, subscribers get
+//! events of all sounds and of the output device.
+use std::sync::mpsc::TrySendError;
+
+use crate::handle::{
+    DeviceEvent, EventSender, PlaySoundCommand, PlaybackEvent, SoundEvent, SoundInfo,
+};
+
+/// Delivers events to requesters of sounds and to subscribers. Subscribers get events through
+/// bounded channels which are never waited on, so slow subscribers miss events instead of
+/// stalling event loop.
+#[derive(Default)]
+pub(crate) struct EventBus {
+    subscribers: Vec<Subscriber>,
+    device_subscribers: Vec<EventSender<DeviceEvent>>,
+}
+
+struct Subscriber {
+    sender: EventSender<SoundEvent>,
+    /// Number of events dropped because subscriber's channel was full
+    missed: u64,
+}
+
+impl EventBus {
+    pub fn subscribe(&mut self, sender: EventSender<SoundEvent>) {
+        self.subscribers.push(Subscriber { sender, missed: 0 });
+    }
+
+    pub fn subscribe_device(&mut self, sender: EventSender<DeviceEvent>) {
+        self.device_subscribers.push(sender);
+    }
+
+    /// Notify requester of the sound and subscribers about lifecycle event of the sound.
+    pub fn notify(&mut self, request: &PlaySoundCommand, event: PlaybackEvent) {
+        let info = SoundInfo {
+            id: request.id,
+            source: request.source.clone(),
+            priority: request.priority.clone(),
+            layer: request.layer.clone(),
+        };
+        self.publish(match &event {
+            PlaybackEvent::Queued => SoundEvent::Queued(info),
+            PlaybackEvent::Started => SoundEvent::Started(info),
+            PlaybackEvent::Finished => SoundEvent::Finished(info),
+            PlaybackEvent::Expired => SoundEvent::Expired(info),
+            PlaybackEvent::Cancelled => SoundEvent::Cancelled(info),
+            PlaybackEvent::Failed(err) => SoundEvent::Failed(info, err.to_string()),
+        });
+        request.notify(event);
+    }
+
+    /// Notify subscribers of device events and all other subscribers about output device event.
+    /// Device event is dropped for a subscriber whose channel is full.
+    pub fn notify_device(&mut self, event: DeviceEvent) {
+        self.device_subscribers.retain(|sender| {
+            !matches!(
+                sender.try_send(event.clone()),
+                Err(TrySendError::Disconnected(_))
+            )
+        });
+        self.publish(SoundEvent::Device(event));
+    }
+
+    /// Send event to all subscribers, forgetting those which dropped their receivers.
+    pub fn publish(&mut self, event: SoundEvent) {
+        self.subscribers.retain_mut(|subscriber| {
+            if subscriber.missed > 0 {
+                match subscriber
+                    .sender
+                    .try_send(SoundEvent::Missed(subscriber.missed))
+                {
+                    Ok(()) => subscriber.missed = 0,
+                    Err(TrySendError::Full(_)) => {
+                        subscriber.missed += 1;
+                        return true;
+                    }
+                    Err(TrySendError::Disconnected(_)) => return false,
+                }
+            }
+            match subscriber.sender.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    subscriber.missed += 1;
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc;
+
+    use crate::handle::{DeviceEvent, PlaySoundCommand, PlaybackEvent, SoundEvent, SoundPriority};
+    use crate::system::events::EventBus;
+
+    #[test]
+    fn slow_subscriber() {
+        let mut events = EventBus::default();
+        let (sender, receiver) = mpsc::sync_channel(2);
+        events.subscribe(sender.into());
+        let (dropped, _) = mpsc::sync_channel(2);
+        events.subscribe(dropped.into());
+
+        let request = PlaySoundCommand::mock("sounds/test.wav", SoundPriority::Default, None);
+        events.notify(&request, PlaybackEvent::Queued);
+        assert_eq!(events.subscribers.len(), 1);
+        events.notify(&request, PlaybackEvent::Started);
+        // channel is full
+        events.publish(SoundEvent::Paused);
+        events.publish(SoundEvent::Resumed);
+        assert!(matches!(receiver.try_recv(), Ok(SoundEvent::Queued(_))));
+        assert!(matches!(receiver.try_recv(), Ok(SoundEvent::Started(_))));
+        events.notify(&request, PlaybackEvent::Finished);
+        assert!(matches!(receiver.try_recv(), Ok(SoundEvent::Missed(2))));
+        assert!(
+            matches!(receiver.try_recv(), Ok(SoundEvent::Finished(info)) if info.priority == SoundPriority::Default)
+        );
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn slow_device_subscriber() {
+        let mut events = EventBus::default();
+        let (sender, receiver) = mpsc::sync_channel(1);
+        events.subscribe_device(sender.into());
+        let (dropped, _) = mpsc::sync_channel(1);
+        events.subscribe_device(dropped.into());
+
+        events.notify_device(DeviceEvent::Lost("unplugged".to_string()));
+        // channel is full, event is dropped but subscriber stays
+        events.notify_device(DeviceEvent::Reopened("default".to_string()));
+        assert_eq!(events.device_subscribers.len(), 1);
+        assert!(matches!(receiver.try_recv(), Ok(DeviceEvent::Lost(_))));
+        events.notify_device(DeviceEvent::Reopened("default".to_string()));
+        assert!(matches!(receiver.try_recv(), Ok(DeviceEvent::Reopened(_))));
+    }
+}
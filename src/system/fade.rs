@@ -0,0 +1,155 @@
+//! Fades and volume ramps applied to sound samples as they are played.
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Durations of fades applied to sounds to avoid clicks caused by abrupt changes of the signal.
+/// Zero duration means the change takes effect immediately, which is the default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Fades {
+    /// Fade in of every sound started or resumed after preemption
+    pub start: Duration,
+    /// Fade out of a sound which is cancelled or stopped while playing
+    pub stop: Duration,
+    /// Fade out of the whole output before it is paused
+    pub pause: Duration,
+    /// Fade in of the whole output once it is resumed
+    pub resume: Duration,
+    /// Ramp from old to new volume of the whole output
+    pub volume: Duration,
+}
+
+/// Target gain shared between event loop and consumers of sound samples. Every consumer ramps
+/// towards the target on its own, see [`Gain`].
+#[derive(Debug)]
+pub(crate) struct Fader {
+    /// Bits of `f32` volume
+    volume: AtomicU32,
+    paused: AtomicBool,
+    /// Duration of ramp towards new target in microseconds
+    ramp: AtomicU64,
+}
+
+impl Fader {
+    pub fn new(volume: f32) -> Self {
+        Self {
+            volume: AtomicU32::new(volume.to_bits()),
+            paused: AtomicBool::new(false),
+            ramp: AtomicU64::new(0),
+        }
+    }
+
+    pub fn volume(&self) -> f32 {
+        f32::from_bits(self.volume.load(Ordering::Acquire))
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Acquire)
+    }
+
+    /// Ramp to new `volume` during `ramp`.
+    pub fn set_volume(&self, volume: f32, ramp: Duration) {
+        self.ramp.store(ramp.as_micros() as u64, Ordering::Release);
+        self.volume.store(volume.to_bits(), Ordering::Release);
+    }
+
+    /// Fade out to silence during `ramp`.
+    pub fn pause(&self, ramp: Duration) {
+        self.ramp.store(ramp.as_micros() as u64, Ordering::Release);
+        self.paused.store(true, Ordering::Release);
+    }
+
+    /// Fade in back to the volume during `ramp`.
+    pub fn resume(&self, ramp: Duration) {
+        self.ramp.store(ramp.as_micros() as u64, Ordering::Release);
+        self.paused.store(false, Ordering::Release);
+    }
+
+    /// Gain consumers ramp towards.
+    fn target(&self) -> f32 {
+        if self.is_paused() {
+            0.0
+        } else {
+            self.volume()
+        }
+    }
+
+    fn ramp(&self) -> Duration {
+        Duration::from_micros(self.ramp.load(Ordering::Acquire))
+    }
+}
+
+/// Gain of a single consumer following target of a [`Fader`]. Changes of the target are ramped
+/// linearly during ramp duration set along with the target.
+#[derive(Debug)]
+pub(crate) struct Gain {
+    current: f32,
+    target: f32,
+    /// Change of gain per frame
+    step: f32,
+}
+
+impl Gain {
+    /// Create gain starting at current target of `fader`.
+    pub fn new(fader: &Fader) -> Self {
+        let target = fader.target();
+        Self {
+            current: target,
+            target,
+            step: 0.0,
+        }
+    }
+
+    /// Advance by one frame and return gain to apply to the frame.
+    pub fn next(&mut self, fader: &Fader, sample_rate: u32) -> f32 {
+        let target = fader.target();
+        if target != self.target {
+            let frames = fader.ramp().as_secs_f32() * sample_rate as f32;
+            self.step = if frames < 1.0 {
+                f32::INFINITY
+            } else {
+                (target - self.current).abs() / frames
+            };
+            self.target = target;
+        }
+        if self.current < self.target {
+            self.current = (self.current + self.step).min(self.target);
+        } else if self.current > self.target {
+            self.current = (self.current - self.step).max(self.target);
+        }
+        self.current
+    }
+
+    /// Returns true if the gain has reached silence.
+    pub fn is_silent(&self) -> bool {
+        self.current == 0.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::system::fade::{Fader, Gain};
+
+    #[test]
+    fn ramp() {
+        let fader = Fader::new(1.0);
+        let mut gain = Gain::new(&fader);
+        assert_eq!(gain.next(&fader, 1000), 1.0);
+        // 4ms at 1kHz is 4 frames
+        fader.set_volume(0.5, Duration::from_millis(4));
+        let ramped: Vec<f32> = (0..5).map(|_| gain.next(&fader, 1000)).collect();
+        assert_eq!(ramped, vec![0.875, 0.75, 0.625, 0.5, 0.5]);
+
+        fader.pause(Duration::from_millis(2));
+        assert_eq!(gain.next(&fader, 1000), 0.25);
+        assert!(!gain.is_silent());
+        assert_eq!(gain.next(&fader, 1000), 0.0);
+        assert!(gain.is_silent());
+        assert_eq!(fader.volume(), 0.5);
+
+        // changes without ramp are immediate
+        fader.resume(Duration::ZERO);
+        assert_eq!(gain.next(&fader, 1000), 0.5);
+    }
+}
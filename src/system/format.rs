@@ -0,0 +1,147 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use rodio::Decoder;
+
+use crate::OrbSoundSystemError;
+
+/// Format of a sound file. Every format, WAV included, requires cargo feature of the same name to
+/// be enabled for decoding. All of them are enabled by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SoundFormat {
+    Wav,
+    Mp3,
+    Flac,
+    Vorbis,
+}
+
+impl SoundFormat {
+    /// Detect format by signature at the beginning of the data.
+    fn from_header(header: &[u8]) -> Option<Self> {
+        match header {
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => Some(Self::Wav),
+            [b'f', b'L', b'a', b'C', ..] => Some(Self::Flac),
+            [b'O', b'g', b'g', b'S', ..] => Some(Self::Vorbis),
+            // either ID3 tag or MPEG frame sync
+            [b'I', b'D', b'3', ..] => Some(Self::Mp3),
+            [0xFF, second, ..] if second & 0xE0 == 0xE0 => Some(Self::Mp3),
+            _ => None,
+        }
+    }
+
+    /// Detect format by file extension.
+    fn from_extension(name: &str) -> Option<Self> {
+        let extension = Path::new(name).extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "wav" | "wave" => Some(Self::Wav),
+            "mp3" => Some(Self::Mp3),
+            "flac" => Some(Self::Flac),
+            "ogg" | "oga" => Some(Self::Vorbis),
+            _ => None,
+        }
+    }
+
+    /// Build decoder of the format.
+    fn decoder<R>(self, data: R) -> Result<Decoder<R>, String>
+    where
+        R: Read + Seek + Send,
+    {
+        match self {
+            #[cfg(feature = "wav")]
+            Self::Wav => Decoder::new_wav(data).map_err(|e| format!("{:?} {}", self, e)),
+            #[cfg(feature = "mp3")]
+            Self::Mp3 => Decoder::new_mp3(data).map_err(|e| format!("{:?} {}", self, e)),
+            #[cfg(feature = "flac")]
+            Self::Flac => Decoder::new_flac(data).map_err(|e| format!("{:?} {}", self, e)),
+            #[cfg(feature = "vorbis")]
+            Self::Vorbis => Decoder::new_vorbis(data).map_err(|e| format!("{:?} {}", self, e)),
+            #[allow(unreachable_patterns)]
+            _ => {
+                drop(data);
+                Err(format!("{:?} support is not enabled", self))
+            }
+        }
+    }
+}
+
+/// Detect format of sound `data` and build decoder for it. Format is detected by the signature at
+/// the beginning of the data, or by extension of `name` if the signature is not recognized.
+pub(crate) fn decode<R>(mut data: R, name: &str) -> Result<Decoder<R>, OrbSoundSystemError>
+where
+    R: Read + Seek + Send,
+{
+    let err = |e: String| OrbSoundSystemError::SoundFileErr(format!("{}: {}", name, e));
+
+    let start = data.stream_position().map_err(|e| err(e.to_string()))?;
+    let mut header = Vec::with_capacity(12);
+    data.by_ref()
+        .take(12)
+        .read_to_end(&mut header)
+        .map_err(|e| err(e.to_string()))?;
+    data.seek(SeekFrom::Start(start))
+        .map_err(|e| err(e.to_string()))?;
+
+    let format = SoundFormat::from_header(&header)
+        .or_else(|| SoundFormat::from_extension(name))
+        .ok_or_else(|| err("Unrecognized format".to_string()))?;
+    format.decoder(data).map_err(err)
+}
+
+#[cfg(test)]
+// Some of the tests decode WAV files
+#[cfg_attr(not(feature = "wav"), allow(unused_imports, dead_code))]
+mod test {
+    use std::fs::File;
+    use std::io::{BufReader, Cursor};
+
+    use crate::system::format::{decode, SoundFormat};
+    use crate::OrbSoundSystemError;
+
+    #[test]
+    fn detect_format() {
+        assert_eq!(
+            SoundFormat::from_header(b"RIFF\x46\x92\x0d\x00WAVEfmt "),
+            Some(SoundFormat::Wav)
+        );
+        assert_eq!(
+            SoundFormat::from_header(b"fLaC\x00\x00\x00\x22"),
+            Some(SoundFormat::Flac)
+        );
+        assert_eq!(
+            SoundFormat::from_header(b"OggS\x00\x02"),
+            Some(SoundFormat::Vorbis)
+        );
+        assert_eq!(
+            SoundFormat::from_header(b"ID3\x04\x00"),
+            Some(SoundFormat::Mp3)
+        );
+        assert_eq!(
+            SoundFormat::from_header(&[0xFF, 0xFB, 0x90, 0x64]),
+            Some(SoundFormat::Mp3)
+        );
+        assert_eq!(SoundFormat::from_header(b"RIFF"), None);
+        assert_eq!(SoundFormat::from_header(b""), None);
+
+        assert_eq!(
+            SoundFormat::from_extension("sounds/beep.MP3"),
+            Some(SoundFormat::Mp3)
+        );
+        assert_eq!(
+            SoundFormat::from_extension("beep.ogg"),
+            Some(SoundFormat::Vorbis)
+        );
+        assert_eq!(SoundFormat::from_extension("beep"), None);
+    }
+
+    #[test]
+    #[cfg(feature = "wav")]
+    fn decode_data() {
+        let file = File::open("sounds/test.wav").unwrap();
+        assert!(decode(BufReader::new(file), "sounds/test.wav").is_ok());
+        // header is not recognized, extension points to a file which is not valid
+        let result = decode(Cursor::new(vec![0u8; 64]), "beep.wav");
+        assert!(matches!(result, Err(OrbSoundSystemError::SoundFileErr(_))));
+        let result = decode(Cursor::new(vec![0u8; 64]), "beep");
+        assert!(matches!(result, Err(OrbSoundSystemError::SoundFileErr(_))));
+    }
+}
@@ -0,0 +1,410 @@
+//! Layer of the system: a queue of sounds played one after another on its own sink. Sounds of
+//! different layers play simultaneously, outputs of all layers are mixed by [`Mixer`].
+//!
+//! [`Mixer`]: crate::system::mixer::Mixer
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rodio::Sink;
+
+use crate::handle::{
+    LayerStatus, PlaySoundCommand, PlaybackEvent, PlaybackId, PlayingSoundStatus,
+    QueuedSoundStatus, SoundPriority, SoundSource,
+};
+use crate::system::events::EventBus;
+use crate::system::fade::{Fader, Fades};
+use crate::system::output::SinkOutput;
+use crate::system::sound::{Playback, Sound, SoundTail};
+use crate::system::{PreemptedSound, PreemptionMode, PreemptionPolicy};
+
+// How often to check whether a finishing sound has been played out once its estimated end passed.
+// Checks get less frequent the longer the sound is overdue, e.g. while output is stalled or device
+// is lost, up to the maximum interval
+const TAIL_POLL_INTERVAL: Duration = Duration::from_millis(5);
+const MAX_TAIL_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+pub(crate) struct Layer {
+    pub name: Arc<str>,
+    pub queue: VecDeque<PlaySoundCommand>,
+    pub current_sound: Option<PlayingSound>,
+    pub finishing_sounds: Vec<FinishingSound>,
+    /// Readers of preempted sounds waiting in the queue to be resumed
+    pub suspended_sounds: HashMap<PlaybackId, Playback>,
+    /// Sink playing sounds of the layer, controls volume and pause state of the layer
+    pub sink: Sink,
+    fades: Fades,
+    /// Loudness preloaded sounds are brought to, in decibels relative to full scale
+    loudness_target: Option<f32>,
+    /// Fader of the whole output, followed by every sound
+    output_fader: Arc<Fader>,
+}
+
+/// Sound being played along with the request it was started by.
+pub(crate) struct PlayingSound {
+    pub request: PlaySoundCommand,
+    pub sound: Sound,
+    /// Set when sound is being interrupted by urgent one
+    pub preempted: bool,
+    /// Set when sound has been cancelled and is fading out
+    pub stopping: bool,
+}
+
+/// Sound which samples are all written to ring buffer, but not yet played out by the sink.
+pub(crate) struct FinishingSound {
+    pub request: PlaySoundCommand,
+    pub tail: SoundTail,
+}
+
+impl Layer {
+    /// Create layer with given `name`. Sounds of the layer fade according to `fades`, get
+    /// normalized to `loudness_target` if given and follow `output_fader`. Returned output of the
+    /// layer sink has to be mixed into system output.
+    pub fn new(
+        name: Arc<str>,
+        fades: Fades,
+        loudness_target: Option<f32>,
+        output_fader: Arc<Fader>,
+    ) -> (Self, SinkOutput) {
+        let (sink, output) = Sink::new_idle();
+        let layer = Self {
+            name,
+            queue: VecDeque::new(),
+            current_sound: None,
+            finishing_sounds: Vec::new(),
+            suspended_sounds: HashMap::new(),
+            sink,
+            fades,
+            loudness_target,
+            output_fader,
+        };
+        (layer, output)
+    }
+
+    /// Drive playback of the layer forward: drop expired sounds, report finished ones, preempt
+    /// current sound if needed, refill ring buffer of currently playing sound and start the next
+    /// one when current has finished.
+    pub fn update(&mut self, now: Instant, preemption: &PreemptionPolicy, events: &mut EventBus) {
+        self.drop_expired(now, events);
+        self.report_finished(events);
+        self.preempt_current(now, preemption, events);
+
+        let finished = self
+            .current_sound
+            .as_mut()
+            .is_some_and(|current| current.sound.fill_buffer());
+        if finished {
+            if let Some(mut current) = self.current_sound.take() {
+                if current.stopping {
+                    // requester has been notified when the sound was stopped
+                    drop(current);
+                } else if let Some(err) = current.sound.take_error() {
+                    // samples left in ring buffer are still played out
+                    events.notify(&current.request, PlaybackEvent::Failed(err));
+                } else if current.preempted {
+                    self.suspend(current, preemption, events);
+                } else {
+                    self.finishing_sounds.push(FinishingSound {
+                        request: current.request,
+                        tail: current.sound.into_tail(now),
+                    });
+                }
+            }
+        }
+
+        if self.current_sound.is_none() {
+            self.play_next_sound(now, events);
+        }
+    }
+
+    /// Returns true if the layer sink has something to play and is not paused.
+    pub fn is_playing(&self) -> bool {
+        !self.sink.empty() && !self.sink.is_paused()
+    }
+
+    /// Returns priorities of sounds heard on the layer right now, which is none while the layer
+    /// is paused.
+    pub fn playing_priorities(&self) -> impl Iterator<Item = &SoundPriority> {
+        let paused = self.sink.is_paused();
+        let current = self.current_sound.iter().map(|current| &current.request);
+        let finishing = self
+            .finishing_sounds
+            .iter()
+            .map(|finishing| &finishing.request);
+        current
+            .chain(finishing)
+            .filter(move |_| !paused)
+            .map(|request| &request.priority)
+    }
+
+    /// Start playing next sound from the queue. Sounds which can not be played are reported to
+    /// their requesters and skipped.
+    fn play_next_sound(&mut self, now: Instant, events: &mut EventBus) {
+        while let Some(request) = self.next_sound(now, events) {
+            let gain = self.sound_gain(&request);
+            let sound = match self.suspended_sounds.remove(&request.id) {
+                Some(reader) => Ok(Sound::resume(
+                    reader,
+                    &self.sink,
+                    gain,
+                    self.fades.start,
+                    &self.output_fader,
+                )),
+                None => Sound::play(
+                    &request.source,
+                    &self.sink,
+                    request.repeat,
+                    request.repeat_gap,
+                    gain,
+                    self.fades.start,
+                    &self.output_fader,
+                ),
+            };
+            match sound {
+                Ok(sound) => {
+                    events.notify(&request, PlaybackEvent::Started);
+                    self.current_sound = Some(PlayingSound {
+                        request,
+                        sound,
+                        preempted: false,
+                        stopping: false,
+                    });
+                    return;
+                }
+                Err(err) => events.notify(&request, PlaybackEvent::Failed(err)),
+            }
+        }
+    }
+
+    /// Returns gain applied to samples of requested sound: gain of the request along with
+    /// loudness normalization of preloaded sounds. Loudness of other sounds is not known until
+    /// they are decoded, so they are not normalized.
+    fn sound_gain(&self, request: &PlaySoundCommand) -> f32 {
+        let normalization = match (&request.source, self.loudness_target) {
+            (SoundSource::Preloaded(sound), Some(target_db)) => sound.normalization_gain(target_db),
+            _ => 1.0,
+        };
+        request.gain * normalization
+    }
+
+    /// Interrupt currently playing sound according to preemption policy if an urgent sound is
+    /// waiting in the queue.
+    fn preempt_current(
+        &mut self,
+        now: Instant,
+        preemption: &PreemptionPolicy,
+        events: &mut EventBus,
+    ) {
+        let urgent_waiting = self
+            .queue
+            .iter()
+            .any(|request| request.priority == SoundPriority::Urgent && !request.is_expired(now));
+        let current = match self.current_sound.as_mut() {
+            Some(current)
+                if urgent_waiting
+                    && !current.preempted
+                    && !current.stopping
+                    && current.request.priority > SoundPriority::Urgent =>
+            {
+                current
+            }
+            _ => return,
+        };
+        match preemption.mode {
+            PreemptionMode::Never => {}
+            PreemptionMode::Interrupt => {
+                current.preempted = true;
+                if let Some(current) = self.current_sound.take() {
+                    self.suspend(current, preemption, events);
+                }
+            }
+            PreemptionMode::FadeOut(duration) => {
+                // sound gets suspended once it has faded out
+                current.preempted = true;
+                current.sound.fade_out(duration);
+            }
+        }
+    }
+
+    /// Deal with a sound interrupted by urgent one according to preemption policy.
+    fn suspend(
+        &mut self,
+        preempted: PlayingSound,
+        preemption: &PreemptionPolicy,
+        events: &mut EventBus,
+    ) {
+        let PlayingSound {
+            mut request, sound, ..
+        } = preempted;
+        if preemption.preempted != PreemptedSound::Drop {
+            // sound has started already, it must not expire while waiting for urgent one
+            request.play_deadline = None;
+        }
+        match preemption.preempted {
+            PreemptedSound::Drop => {
+                drop(sound);
+                events.notify(&request, PlaybackEvent::Cancelled);
+            }
+            PreemptedSound::Restart => {
+                drop(sound);
+                self.queue.push_back(request);
+            }
+            PreemptedSound::Resume => {
+                self.suspended_sounds
+                    .insert(request.id, sound.into_reader());
+                self.queue.push_back(request);
+            }
+        }
+    }
+
+    /// Notify requesters of sounds which have been played out by the sink.
+    fn report_finished(&mut self, events: &mut EventBus) {
+        self.finishing_sounds.retain(|finishing| {
+            let played = finishing.tail.is_played();
+            if played {
+                events.notify(&finishing.request, PlaybackEvent::Finished);
+            }
+            !played
+        });
+    }
+
+    /// Returns the moment the layer needs attention even if no command arrives. That is the
+    /// earliest of:
+    ///
+    /// - time when ring buffer of currently playing sound should be refilled (unless paused)
+    /// - time when a finishing sound is expected to be played out (unless paused)
+    /// - play deadline of a queued sound
+    ///
+    /// Layer is considered paused if either its sink or the whole output is paused.
+    pub fn next_wakeup(&self, now: Instant, output_paused: bool) -> Option<Instant> {
+        let paused = output_paused || self.sink.is_paused();
+        let refill = self
+            .current_sound
+            .as_ref()
+            .filter(|_| !paused)
+            .map(|current| now + current.sound.refill_in());
+        let finish = self
+            .finishing_sounds
+            .iter()
+            .filter(|_| !paused)
+            .map(|finishing| {
+                let overdue = now.saturating_duration_since(finishing.tail.ends_at());
+                let poll = (overdue / 2).clamp(TAIL_POLL_INTERVAL, MAX_TAIL_POLL_INTERVAL);
+                finishing.tail.ends_at().max(now + poll)
+            })
+            .min();
+        let deadline = self
+            .queue
+            .iter()
+            .filter_map(|command| command.play_deadline)
+            .min();
+        refill.into_iter().chain(finish).chain(deadline).min()
+    }
+
+    /// Returns snapshot of the layer state.
+    pub fn status(&self, now: Instant) -> LayerStatus {
+        let current = self
+            .current_sound
+            .as_ref()
+            .map(|current| PlayingSoundStatus {
+                id: current.request.id,
+                source: current.request.source.clone(),
+                priority: current.request.priority.clone(),
+                elapsed: current.sound.elapsed(),
+            });
+        let mut queue: Vec<_> = self.queue.iter().collect();
+        queue.sort();
+        let queue = queue
+            .into_iter()
+            .map(|request| QueuedSoundStatus {
+                id: request.id,
+                source: request.source.clone(),
+                priority: request.priority.clone(),
+                remaining: request
+                    .play_deadline
+                    .map(|deadline| deadline.saturating_duration_since(now)),
+            })
+            .collect();
+        LayerStatus {
+            name: self.name.clone(),
+            volume: self.sink.volume(),
+            paused: self.sink.is_paused(),
+            current,
+            queue,
+        }
+    }
+
+    /// Cancel queued and currently playing sounds matching `predicate`.
+    pub fn cancel(&mut self, predicate: impl Fn(&PlaySoundCommand) -> bool, events: &mut EventBus) {
+        self.remove_queued(&predicate, || PlaybackEvent::Cancelled, events);
+        let cancel_current = self
+            .current_sound
+            .as_ref()
+            .is_some_and(|current| predicate(&current.request));
+        if cancel_current {
+            self.stop_current(events);
+        }
+    }
+
+    /// Stop currently playing sound. Without stop fade dropping the sound abandons its ring
+    /// buffer, so the sink plays out what is left in the buffer and moves on. Otherwise the sound
+    /// stays current until it has faded out.
+    pub fn stop_current(&mut self, events: &mut EventBus) {
+        match self.current_sound.as_mut() {
+            Some(current) if current.stopping => {}
+            Some(current) if !self.fades.stop.is_zero() => {
+                current.stopping = true;
+                current.sound.stop(self.fades.stop);
+                events.notify(&current.request, PlaybackEvent::Cancelled);
+            }
+            Some(_) => {
+                if let Some(PlayingSound { request, sound, .. }) = self.current_sound.take() {
+                    drop(sound);
+                    events.notify(&request, PlaybackEvent::Cancelled);
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// Drops queued sounds which play deadline has passed.
+    pub fn drop_expired(&mut self, now: Instant, events: &mut EventBus) {
+        self.remove_queued(
+            |command| command.is_expired(now),
+            || PlaybackEvent::Expired,
+            events,
+        );
+    }
+
+    /// Removes queued sounds matching `predicate` and notifies their requesters with `event`.
+    pub fn remove_queued(
+        &mut self,
+        predicate: impl Fn(&PlaySoundCommand) -> bool,
+        event: impl Fn() -> PlaybackEvent,
+        events: &mut EventBus,
+    ) {
+        let suspended_sounds = &mut self.suspended_sounds;
+        self.queue.retain(|command| {
+            let remove = predicate(command);
+            if remove {
+                suspended_sounds.remove(&command.id);
+                events.notify(command, event());
+            }
+            !remove
+        });
+    }
+
+    /// Returns next sound to be played by sorting queue and taking first sound. Checks play
+    /// deadlines and drops "expired" sounds
+    pub fn next_sound(&mut self, now: Instant, events: &mut EventBus) -> Option<PlaySoundCommand> {
+        self.queue.make_contiguous().sort();
+        while let Some(next) = self.queue.pop_front() {
+            if !next.is_expired(now) {
+                return Some(next);
+            }
+            self.suspended_sounds.remove(&next.id);
+            events.notify(&next, PlaybackEvent::Expired);
+        }
+        None
+    }
+}
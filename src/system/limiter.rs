@@ -0,0 +1,275 @@
+//! Protection of the speaker against clipping, applied to mixed output after volume.
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rodio::Source;
+
+use crate::handle::LimiterStatus;
+
+// Part of the ceiling peaks are limited to. Samples above it which get past the limiter are soft
+// clipped into the rest of the range below the ceiling
+const SOFT_CLIP_KNEE: f32 = 0.9;
+
+/// Settings of output limiter, see [`OrbSoundSystemBuilder::limiter()`].
+///
+/// [`OrbSoundSystemBuilder::limiter()`]: crate::system::OrbSoundSystemBuilder::limiter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LimiterSettings {
+    /// Level output never exceeds, in decibels relative to full scale. Peaks are limited to 90% of
+    /// it, about 0.9dB below, and the rest of the range is left to the soft clipper which bends
+    /// samples getting past the limiter. E.g. ceiling of 0dB limits peaks to -0.9dB
+    pub ceiling_db: f32,
+    /// How far ahead the limiter looks for peaks. Output is delayed by that time
+    pub lookahead: Duration,
+    /// Time it takes to restore gain once peaks are over
+    pub release: Duration,
+}
+
+impl Default for LimiterSettings {
+    fn default() -> Self {
+        Self {
+            ceiling_db: -1.0,
+            lookahead: Duration::from_millis(5),
+            release: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Counters of limiter activity shared between the limiter and event loop.
+#[derive(Debug, Default)]
+pub(crate) struct LimiterStats {
+    sample_rate: u32,
+    engaged: AtomicU64,
+    limited_frames: AtomicU64,
+    clipped: AtomicU64,
+}
+
+impl LimiterStats {
+    pub fn status(&self) -> LimiterStatus {
+        let limited_frames = self.limited_frames.load(Ordering::Relaxed);
+        LimiterStatus {
+            engaged: self.engaged.load(Ordering::Relaxed),
+            limited: Duration::from_micros(
+                limited_frames * 1_000_000 / self.sample_rate.max(1) as u64,
+            ),
+            clipped: self.clipped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Look-ahead peak limiter followed by soft clipper. Gain needed to keep every frame below the
+/// threshold is held for the look-ahead time and smoothed over the same time, so it ramps down
+/// before a peak arrives from the delay line. Never ends, produces silence once the source ends.
+pub(crate) struct Limiter<S> {
+    source: S,
+    stats: Arc<LimiterStats>,
+    channels: usize,
+    threshold: f32,
+    ceiling: f32,
+    /// Increase of gain per frame during release
+    release_step: f32,
+    /// Look-ahead time in frames
+    lookahead: usize,
+    /// Samples of the frames waiting to be played
+    delay: VecDeque<f32>,
+    /// Gains required by recent frames along with frame numbers, ascending, used to find the
+    /// lowest gain within look-ahead time
+    required: VecDeque<(u64, f32)>,
+    /// Held gains of recent frames and their sum, averaged to smooth gain changes
+    held: VecDeque<f32>,
+    held_sum: f64,
+    /// Number of frames processed so far
+    frames: u64,
+    limiting: bool,
+    /// Processed frame being returned sample by sample
+    frame: Vec<f32>,
+    position: usize,
+}
+
+impl<S> Limiter<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(source: S, settings: LimiterSettings) -> Self {
+        let channels = source.channels().max(1) as usize;
+        let sample_rate = source.sample_rate().max(1);
+        let frames_of = |duration: Duration| (duration.as_secs_f32() * sample_rate as f32) as usize;
+        let lookahead = frames_of(settings.lookahead).max(1);
+        let ceiling = 10f32.powf(settings.ceiling_db / 20.0);
+        Self {
+            source,
+            stats: Arc::new(LimiterStats {
+                sample_rate,
+                ..LimiterStats::default()
+            }),
+            channels,
+            threshold: ceiling * SOFT_CLIP_KNEE,
+            ceiling,
+            release_step: 1.0 / frames_of(settings.release).max(1) as f32,
+            lookahead,
+            delay: vec![0.0; lookahead * channels].into(),
+            required: VecDeque::new(),
+            held: vec![1.0; lookahead].into(),
+            held_sum: lookahead as f64,
+            frames: 0,
+            limiting: false,
+            frame: vec![0.0; channels],
+            position: channels,
+        }
+    }
+
+    /// Returns counters of limiter activity, shared with event loop.
+    pub fn stats(&self) -> Arc<LimiterStats> {
+        self.stats.clone()
+    }
+
+    fn process_frame(&mut self) {
+        let mut peak = 0f32;
+        for _ in 0..self.channels {
+            let sample = self.source.next().unwrap_or(0.0);
+            peak = peak.max(sample.abs());
+            self.delay.push_back(sample);
+        }
+        let required = if peak > self.threshold {
+            self.threshold / peak
+        } else {
+            1.0
+        };
+
+        // lowest gain required by frames within look-ahead time, including the one leaving the
+        // delay line
+        while self
+            .required
+            .back()
+            .is_some_and(|&(_, gain)| gain >= required)
+        {
+            self.required.pop_back();
+        }
+        self.required.push_back((self.frames, required));
+        while self
+            .required
+            .front()
+            .is_some_and(|&(frame, _)| frame + (self.lookahead as u64) < self.frames)
+        {
+            self.required.pop_front();
+        }
+        let lowest = self.required.front().map_or(1.0, |&(_, gain)| gain);
+        let last_held = self.held.back().copied().unwrap_or(1.0);
+        let held = lowest.min(last_held + self.release_step);
+        self.held.push_back(held);
+        self.held_sum += held as f64;
+        if let Some(oldest) = self.held.pop_front() {
+            self.held_sum -= oldest as f64;
+        }
+        let gain = (self.held_sum / self.lookahead as f64).min(1.0) as f32;
+        self.frames += 1;
+
+        let limiting = gain < 1.0 - f32::EPSILON;
+        if limiting {
+            if !self.limiting {
+                self.stats.engaged.fetch_add(1, Ordering::Relaxed);
+            }
+            self.stats.limited_frames.fetch_add(1, Ordering::Relaxed);
+        }
+        self.limiting = limiting;
+
+        for sample in self.frame.iter_mut() {
+            *sample = self.delay.pop_front().unwrap_or(0.0) * gain;
+            if sample.abs() > self.threshold {
+                *sample = soft_clip(*sample, self.threshold, self.ceiling);
+                self.stats.clipped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.position = 0;
+    }
+}
+
+/// Smoothly bends `sample` above `knee` so that it never exceeds `ceiling`.
+fn soft_clip(sample: f32, knee: f32, ceiling: f32) -> f32 {
+    let range = ceiling - knee;
+    let excess = sample.abs() - knee;
+    sample.signum() * (knee + range * (excess / range).tanh())
+}
+
+impl<S> Iterator for Limiter<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position == self.frame.len() {
+            self.process_frame();
+        }
+        let sample = self.frame[self.position];
+        self.position += 1;
+        Some(sample)
+    }
+}
+
+impl<S> Source for Limiter<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.stats.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use rodio::buffer::SamplesBuffer;
+
+    use crate::system::limiter::{soft_clip, Limiter, LimiterSettings};
+
+    #[test]
+    fn limit() {
+        let settings = LimiterSettings {
+            ceiling_db: 0.0,
+            lookahead: Duration::from_millis(4),
+            release: Duration::from_millis(10),
+        };
+        let mut samples = vec![0.5f32; 20];
+        samples[10] = 1.8;
+        let limiter = Limiter::new(SamplesBuffer::new(1, 1000, samples), settings);
+        let stats = limiter.stats();
+        let output: Vec<f32> = limiter.take(40).collect();
+
+        // output is delayed by look-ahead time
+        assert!(output[..4].iter().all(|&sample| sample == 0.0));
+        assert!(output[4..10].iter().all(|&sample| sample == 0.5));
+        // gain ramps down before the peak and gets back up after it
+        assert!(output[10..14].windows(2).all(|pair| pair[1] < pair[0]));
+        assert!((output[14] - 0.9).abs() < 0.0001);
+        assert!(output[15..23].windows(2).all(|pair| pair[1] > pair[0]));
+        assert!(output.iter().all(|&sample| sample <= 0.9 + 0.0001));
+
+        let status = stats.status();
+        assert_eq!(status.engaged, 1);
+        assert!(status.limited > Duration::from_millis(8));
+        assert_eq!(status.clipped, 0);
+    }
+
+    #[test]
+    fn soft_clip_below_ceiling() {
+        assert_eq!(soft_clip(0.95, 0.9, 1.0), 0.9 + 0.1 * 0.5f32.tanh());
+        assert!(soft_clip(100.0, 0.9, 1.0) <= 1.0);
+        assert!(soft_clip(-100.0, 0.9, 1.0) >= -1.0);
+    }
+}
@@ -0,0 +1,354 @@
+//! Mixing stage combining outputs of all layers into a single source played by the main sink.
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rodio::Source;
+
+use crate::system::output::SinkOutput;
+
+/// Format sounds are mixed in unless configured otherwise, see
+/// [`OrbSoundSystemBuilder::mix_format()`].
+///
+/// [`OrbSoundSystemBuilder::mix_format()`]: crate::system::OrbSoundSystemBuilder::mix_format
+pub const DEFAULT_MIX_CHANNELS: u16 = 2;
+pub const DEFAULT_MIX_SAMPLE_RATE: u32 = 44100;
+
+/// Source summing outputs of layer sinks. Every layer output is converted to the mix format on
+/// the fly and ducked as set through [`MixerControls`]. Never ends, produces silence while no
+/// layer plays anything.
+pub(crate) struct Mixer {
+    channels: u16,
+    sample_rate: u32,
+    inputs: Vec<MixerInput>,
+    controls: Arc<MixerControls>,
+    /// Mixed frame being returned sample by sample
+    frame: Vec<f32>,
+    position: usize,
+}
+
+impl Mixer {
+    pub fn new(channels: u16, sample_rate: u32, inputs: Vec<SinkOutput>) -> Self {
+        let inputs = inputs
+            .into_iter()
+            .map(|source| MixerInput::new(source, channels, sample_rate))
+            .collect::<Vec<_>>();
+        let controls = Arc::new(MixerControls {
+            ducking: inputs.iter().map(|_| DuckingControl::default()).collect(),
+        });
+        Self {
+            channels,
+            sample_rate,
+            inputs,
+            controls,
+            frame: vec![0.0; channels as usize],
+            position: channels as usize,
+        }
+    }
+
+    /// Returns controls of the mixer, shared with event loop.
+    pub fn controls(&self) -> Arc<MixerControls> {
+        self.controls.clone()
+    }
+
+    fn mix_frame(&mut self) {
+        self.frame.fill(0.0);
+        for (input, ducking) in self.inputs.iter_mut().zip(&self.controls.ducking) {
+            let gain = input.envelope.next_gain(&ducking.get(), self.sample_rate);
+            input.mix_into(&mut self.frame, gain);
+        }
+        self.position = 0;
+    }
+}
+
+/// Settings of the mixer changed by event loop while the mixer plays. Kept in atomics, so the
+/// audio thread never waits for event loop.
+#[derive(Debug)]
+pub(crate) struct MixerControls {
+    /// Ducking of every input, in the order inputs were given to the mixer
+    ducking: Vec<DuckingControl>,
+}
+
+impl MixerControls {
+    pub fn ducking(&self) -> Vec<Ducking> {
+        self.ducking.iter().map(DuckingControl::get).collect()
+    }
+
+    pub fn set_ducking(&self, ducking: Vec<Ducking>) {
+        for (control, ducking) in self.ducking.iter().zip(ducking) {
+            control.set(ducking);
+        }
+    }
+}
+
+/// [`Ducking`] of a single input shared with the audio thread.
+#[derive(Debug, Default)]
+struct DuckingControl {
+    /// Bits of `f32` amount
+    amount_db: AtomicU32,
+    /// Attack and release times in microseconds
+    attack: AtomicU64,
+    release: AtomicU64,
+}
+
+impl DuckingControl {
+    fn get(&self) -> Ducking {
+        // amount is loaded first, so times stored along with it are seen as well
+        let amount_db = f32::from_bits(self.amount_db.load(Ordering::Acquire));
+        Ducking {
+            amount_db,
+            attack: Duration::from_micros(self.attack.load(Ordering::Acquire)),
+            release: Duration::from_micros(self.release.load(Ordering::Acquire)),
+        }
+    }
+
+    fn set(&self, ducking: Ducking) {
+        self.attack
+            .store(ducking.attack.as_micros() as u64, Ordering::Release);
+        self.release
+            .store(ducking.release.as_micros() as u64, Ordering::Release);
+        self.amount_db
+            .store(ducking.amount_db.to_bits(), Ordering::Release);
+    }
+}
+
+/// Attenuation of mixer input. Changes of the amount are ramped linearly in decibels during
+/// attack time when attenuation grows and during release time when it drops.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub(crate) struct Ducking {
+    pub amount_db: f32,
+    pub attack: Duration,
+    pub release: Duration,
+}
+
+/// Current state of ramping attenuation of an input towards its [`Ducking`] amount.
+#[derive(Default)]
+struct DuckingEnvelope {
+    current_db: f32,
+    target_db: f32,
+    /// Change of attenuation per frame
+    step_db: f32,
+    gain: Option<f32>,
+}
+
+impl DuckingEnvelope {
+    /// Advance by one frame and return gain to apply to the frame.
+    fn next_gain(&mut self, ducking: &Ducking, sample_rate: u32) -> f32 {
+        if ducking.amount_db != self.target_db {
+            let duration = if ducking.amount_db > self.current_db {
+                ducking.attack
+            } else {
+                ducking.release
+            };
+            let frames = (duration.as_secs_f32() * sample_rate as f32).max(1.0);
+            self.step_db = (ducking.amount_db - self.current_db).abs() / frames;
+            self.target_db = ducking.amount_db;
+        }
+        if self.current_db != self.target_db {
+            self.current_db = if self.current_db < self.target_db {
+                (self.current_db + self.step_db).min(self.target_db)
+            } else {
+                (self.current_db - self.step_db).max(self.target_db)
+            };
+            self.gain = None;
+        }
+        let current_db = self.current_db;
+        *self
+            .gain
+            .get_or_insert_with(|| 10f32.powf(-current_db / 20.0))
+    }
+}
+
+impl Iterator for Mixer {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position == self.frame.len() {
+            self.mix_frame();
+        }
+        let sample = self.frame[self.position];
+        self.position += 1;
+        Some(sample)
+    }
+}
+
+impl Source for Mixer {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Output of a layer sink converted to the mix format. Format of the sink output is checked at
+/// every frame, because the sink reports format of a new sound only once its first sample is
+/// pulled. Sample rate is converted by linear interpolation between neighbouring frames. Frames
+/// are kept in buffers allocated once, so mixing does not allocate on the audio thread.
+struct MixerInput {
+    source: SinkOutput,
+    envelope: DuckingEnvelope,
+    channels: u16,
+    sample_rate: u32,
+    /// Frames of the source around current position, already converted to mix channels, along
+    /// with their sample rates
+    current: Vec<f32>,
+    current_rate: u32,
+    following: Vec<f32>,
+    following_rate: u32,
+    /// Frame of the source in its own channels
+    raw: Vec<f32>,
+    /// Set once the first frames are pulled
+    started: bool,
+    /// Position between current and following frame, from 0 to 1
+    position: f64,
+}
+
+impl MixerInput {
+    fn new(source: SinkOutput, channels: u16, sample_rate: u32) -> Self {
+        Self {
+            source,
+            envelope: DuckingEnvelope::default(),
+            channels,
+            sample_rate,
+            current: vec![0.0; channels as usize],
+            current_rate: sample_rate,
+            following: vec![0.0; channels as usize],
+            following_rate: sample_rate,
+            raw: Vec::with_capacity(channels.max(8) as usize),
+            started: false,
+            position: 0.0,
+        }
+    }
+
+    /// Add next frame in the mix format, scaled by `gain`, to `mixed` frame.
+    fn mix_into(&mut self, mixed: &mut [f32], gain: f32) {
+        if !self.started {
+            self.started = true;
+            self.read_following();
+            self.advance();
+        }
+        let position = self.position as f32;
+        for ((mixed, current), following) in
+            mixed.iter_mut().zip(&self.current).zip(&self.following)
+        {
+            *mixed += (current * (1.0 - position) + following * position) * gain;
+        }
+
+        self.position += self.current_rate as f64 / self.sample_rate as f64;
+        while self.position >= 1.0 {
+            self.advance();
+            self.position -= 1.0;
+        }
+    }
+
+    /// Make following frame the current one and pull the next following frame.
+    fn advance(&mut self) {
+        std::mem::swap(&mut self.current, &mut self.following);
+        self.current_rate = self.following_rate;
+        self.read_following();
+    }
+
+    /// Pull single frame of the source into following frame and convert it to mix channels.
+    /// Source which has ended produces silence.
+    fn read_following(&mut self) {
+        let first = match self.source.next() {
+            Some(sample) => sample,
+            None => {
+                self.following.fill(0.0);
+                self.following_rate = self.sample_rate;
+                return;
+            }
+        };
+        let channels = self.source.channels().max(1) as usize;
+        self.following_rate = self.source.sample_rate().max(1);
+        self.raw.clear();
+        self.raw.push(first);
+        for _ in 1..channels {
+            let sample = self.source.next().unwrap_or(0.0);
+            self.raw.push(sample);
+        }
+        if channels == self.channels as usize {
+            self.following.copy_from_slice(&self.raw);
+        } else if self.channels == 1 {
+            self.following[0] = self.raw.iter().sum::<f32>() / channels as f32;
+        } else {
+            for (channel, sample) in self.following.iter_mut().enumerate() {
+                *sample = self.raw[channel % channels];
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rodio::buffer::SamplesBuffer;
+    use rodio::{Sink, Source};
+
+    use std::time::Duration;
+
+    use crate::system::mixer::{Ducking, Mixer};
+
+    #[test]
+    fn mix() {
+        let (first, first_output) = Sink::new_idle();
+        let (second, second_output) = Sink::new_idle();
+        let mut mixer = Mixer::new(2, 1000, vec![first_output, second_output]);
+        assert_eq!((mixer.channels(), mixer.sample_rate()), (2, 1000));
+
+        first.append(SamplesBuffer::new(2, 1000, vec![0.25f32, 0.5, 0.25, 0.5]));
+        // mono sound is played on both channels
+        second.append(SamplesBuffer::new(1, 1000, vec![0.25f32, 0.25]));
+        // sink applies volume of the layer
+        second.set_volume(2.0);
+        let mixed: Vec<f32> = mixer.by_ref().take(4).collect();
+        assert_eq!(mixed, vec![0.75, 1.0, 0.75, 1.0]);
+        // silence once nothing is playing
+        assert!(mixer.by_ref().take(10).all(|sample| sample == 0.0));
+    }
+
+    #[test]
+    fn convert_sample_rate() {
+        let (sink, output) = Sink::new_idle();
+        let mut mixer = Mixer::new(1, 1000, vec![output]);
+        sink.append(SamplesBuffer::new(1, 500, vec![0.0f32, 1.0, 0.0]));
+        let mixed: Vec<f32> = mixer.by_ref().take(5).collect();
+        assert_eq!(mixed, vec![0.0, 0.5, 1.0, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn duck() {
+        let (sink, output) = Sink::new_idle();
+        let mut mixer = Mixer::new(1, 1000, vec![output]);
+        sink.append(SamplesBuffer::new(1, 1000, vec![1.0f32; 100]));
+        let controls = mixer.controls();
+        // 20dB during 2ms at 1kHz is 2 frames
+        controls.set_ducking(vec![Ducking {
+            amount_db: 20.0,
+            attack: Duration::from_millis(2),
+            release: Duration::from_millis(4),
+        }]);
+        let ducked: Vec<f32> = mixer.by_ref().take(3).collect();
+        assert!((ducked[0] - 0.316).abs() < 0.001);
+        assert!((ducked[1] - 0.1).abs() < 0.001);
+        assert!((ducked[2] - 0.1).abs() < 0.001);
+
+        // release keeps times of the rule
+        controls.set_ducking(vec![Ducking {
+            amount_db: 0.0,
+            ..controls.ducking()[0]
+        }]);
+        let released: Vec<f32> = mixer.by_ref().take(5).collect();
+        assert!((released[1] - 0.316).abs() < 0.001);
+        assert_eq!(released[3..], [1.0, 1.0]);
+    }
+}
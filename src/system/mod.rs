@@ -1,162 +1,651 @@
-use std::collections::VecDeque;
-use std::sync::mpsc::{self, Receiver, TryRecvError};
-use std::thread;
-use std::time::Instant;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
 
-use rodio::{OutputStream, Sink};
+use rodio::Sink;
 
-use crate::handle::{OrbSoundSystemHandle, PlaySoundCommand, SoundCommand};
+use crate::clock::Clock;
+use crate::handle::{
+    EventSender, OrbSoundSystemHandle, PlaybackEvent, SoundCommand, SoundEvent, SoundSource,
+    SoundSystemStatus,
+};
+use crate::system::device::DeviceOutput;
+use crate::system::events::EventBus;
+use crate::system::fade::Fader;
+use crate::system::layer::Layer;
+use crate::system::limiter::{Limiter, LimiterStats};
+use crate::system::mixer::{Ducking, Mixer, MixerControls};
+use crate::system::state::{SavedLayer, SavedState, StateFile};
+use crate::system::volume::Volume;
 use crate::OrbSoundSystemError;
-use crate::system::sound::Sound;
 
-mod sound;
+pub use builder::OrbSoundSystemBuilder;
+pub use ducking::{DuckingRule, DuckingTrigger};
+pub use fade::Fades;
+pub use limiter::LimiterSettings;
+pub use mixer::{DEFAULT_MIX_CHANNELS, DEFAULT_MIX_SAMPLE_RATE};
+pub use null::NullOutput;
+pub use output::{OutputBackend, SinkOutput};
+pub use preemption::{PreemptedSound, PreemptionMode, PreemptionPolicy};
+pub use render::{RenderBuffer, RenderOutput};
+pub use volume::VolumeLimits;
+
+mod builder;
+mod device;
+mod ducking;
+mod events;
+mod fade;
+mod format;
+mod layer;
+mod limiter;
+mod mixer;
+mod null;
+mod output;
+mod preemption;
+mod render;
+pub(crate) mod sound;
+mod state;
+mod volume;
 
 /// Type representing Orb's sound system. It runs event loop, receives playback commands, controls
 /// playback and decides what file should be played next.
+///
+/// Sounds are played on layers. Each layer has its own queue, volume and pause state, so sounds
+/// of different layers play simultaneously while sounds of the same layer never overlap. Layers
+/// are mixed together and played through the main sink, optionally protected by output limiter.
+/// Volume and pause state of the whole output are applied to samples of every sound, so changes
+/// of them are faded.
 pub struct OrbSoundSystem {
     command_receiver: Receiver<SoundCommand>,
-    queue: VecDeque<PlaySoundCommand>,
-    current_sound: Option<Sound>,
+    /// Layers of the system, default layer goes first
+    layers: Vec<Layer>,
+    preemption: PreemptionPolicy,
+    ducking: Vec<DuckingRule>,
+    /// Controls of the mixer combining layers
+    mixer: Arc<MixerControls>,
+    /// Activity of output limiter, if enabled
+    limiter: Option<Arc<LimiterStats>>,
+    fades: Fades,
+    volume: Volume,
+    /// Volume and pause state of the whole output applied to sounds
+    fader: Arc<Fader>,
+    /// Moment the main sink is paused, once output has faded out
+    pause_at: Option<Instant>,
+    /// File volume and layer settings are saved to, if any
+    state_file: Option<StateFile>,
+    /// Settings as they are in the state file
+    saved_state: SavedState,
+    /// Moment changed settings are written to the state file
+    save_at: Option<Instant>,
+    /// Senders waiting for the system to stop
+    shutdown_requesters: Vec<EventSender<()>>,
+    events: EventBus,
     sink: Sink,
-    _output_stream: OutputStream,
+    output: Box<dyn OutputBackend>,
+    /// Set when output falls back to [`NullOutput`] because no sound device works
+    null_output: bool,
+    clock: Arc<dyn Clock>,
 }
 
 impl OrbSoundSystem {
-    /// Initialize and run Orb's sound system using default sound device for output. Spawns a thread
-    /// and runs event loop on it. Returns either [`OrbSoundSystemHandle`] or some sort of
-    /// initialization error.
+    /// Initialize and run Orb's sound system using default sound device for output, or discarding
+    /// output if there is no working device. Spawns a thread and runs event loop on it. Returns
+    /// either [`OrbSoundSystemHandle`] or some sort of initialization error.
     pub fn run() -> Result<OrbSoundSystemHandle, OrbSoundSystemError> {
-        let (command_sender, command_receiver) = mpsc::channel::<SoundCommand>();
-        let (err_sender, err_receiver) = mpsc::channel::<Option<OrbSoundSystemError>>();
+        Self::builder().run()
+    }
 
-        thread::spawn(move || {
-            match OrbSoundSystem::init(command_receiver) {
-                Ok(system) => {
-                    err_sender.send(None).unwrap();
-                    system.run_event_loop();
-                }
-                Err(e) => {
-                    err_sender.send(Some(e)).unwrap();
-                }
-            }
-        });
+    /// Returns builder which allows to configure the system before running it.
+    pub fn builder() -> OrbSoundSystemBuilder {
+        OrbSoundSystemBuilder::default()
+    }
 
-        match err_receiver.recv().unwrap() {
-            Some(err) => Err(err),
-            None => Ok(OrbSoundSystemHandle { command_sender })
-        }
+    /// Returns names of available output devices, which can be passed to
+    /// [`OrbSoundSystemBuilder::device()`].
+    pub fn list_output_devices() -> Result<Vec<String>, OrbSoundSystemError> {
+        device::list_output_devices()
     }
 
-    /// Initialize default sound device.
-    fn init(command_receiver: Receiver<SoundCommand>) -> Result<Self, OrbSoundSystemError> {
-        // OutputStream must be initialized on event loop thread, otherwise there is no sound output (bug?)
-        let (stream, stream_handle) =
-            OutputStream::try_default().map_err(|e| OrbSoundSystemError::StreamErr(e))?;
-        let sink = Sink::try_new(&stream_handle).map_err(|e| OrbSoundSystemError::PlayErr(e))?;
+    /// Initialize output backend selected by the builder and layers mixed into it.
+    fn init(
+        command_receiver: Receiver<SoundCommand>,
+        waker: Weak<Sender<SoundCommand>>,
+        mut builder: OrbSoundSystemBuilder,
+    ) -> Result<Self, OrbSoundSystemError> {
+        // Output stream must be initialized on event loop thread, otherwise there is no sound output (bug?)
+        let (sink, output, null_output): (Sink, Box<dyn OutputBackend>, _) =
+            match builder.output.take() {
+                Some(mut output) => {
+                    let (sink, sink_output) = Sink::new_idle();
+                    output.start(sink_output)?;
+                    (sink, output as _, false)
+                }
+                None => Self::start_device(&builder, waker)?,
+            };
+
+        let volume = Volume::new(builder.volume_limits);
+        let fader = Arc::new(Fader::new(volume.gain()));
+        let (layers, layer_outputs): (Vec<_>, Vec<_>) = builder
+            .layers()
+            .into_iter()
+            .map(|name| Layer::new(name, builder.fades, builder.loudness_target, fader.clone()))
+            .unzip();
+        let (channels, sample_rate) = builder.mix_format;
+        let mixer = Mixer::new(channels, sample_rate, layer_outputs);
+        let mixer_controls = mixer.controls();
+        let limiter = match builder.limiter {
+            Some(settings) => {
+                let limiter = Limiter::new(mixer, settings);
+                let stats = limiter.stats();
+                sink.append(limiter);
+                Some(stats)
+            }
+            None => {
+                sink.append(mixer);
+                None
+            }
+        };
 
-        Ok(Self {
+        let state_file = builder.state_file.map(StateFile::new);
+        let saved = state_file.as_ref().map(StateFile::load);
+        let mut system = Self {
             command_receiver,
-            queue: VecDeque::new(),
-            current_sound: None,
+            layers,
+            preemption: builder.preemption,
+            ducking: builder.ducking,
+            mixer: mixer_controls,
+            limiter,
+            fades: builder.fades,
+            volume,
+            fader,
+            pause_at: None,
+            state_file,
+            saved_state: SavedState::default(),
+            save_at: None,
+            shutdown_requesters: Vec::new(),
+            events: EventBus::default(),
             sink,
-            _output_stream: stream,
-        })
+            output,
+            null_output,
+            clock: builder.clock,
+        };
+        if let Some(saved) = saved {
+            system.restore_state(saved);
+        }
+        system.saved_state = system.current_state();
+        Ok(system)
+    }
+
+    /// Start output on sound device selected by the builder. Falls back to [`NullOutput`] if no
+    /// device works, unless the builder requires a device. Returns true along with the output in
+    /// that case.
+    fn start_device(
+        builder: &OrbSoundSystemBuilder,
+        waker: Weak<Sender<SoundCommand>>,
+    ) -> Result<(Sink, Box<dyn OutputBackend>, bool), OrbSoundSystemError> {
+        let (sink, sink_output) = Sink::new_idle();
+        let mut device = DeviceOutput::new(builder.devices(), waker);
+        match device.start(sink_output) {
+            Ok(()) => Ok((sink, Box::new(device), false)),
+            Err(_) if !builder.require_device => {
+                // Output of the first sink is gone along with the device
+                let (sink, sink_output) = Sink::new_idle();
+                let mut null = NullOutput::new();
+                null.start(sink_output)?;
+                Ok((sink, Box::new(null), true))
+            }
+            Err(e) => Err(e),
+        }
     }
 
     /// Main event loop. Responsible for:
     ///
     /// - Processing incoming commands
-    /// - Filling ring buffer of currently playing sound (if any)
-    /// - Playing next sound when previous has finished
+    /// - Reopening output device when its stream fails
+    /// - Rendering output of non real time backends
+    /// - Filling ring buffers of currently playing sounds (if any)
+    /// - Playing next sound of a layer when previous has finished
+    ///
+    /// Between iterations the loop blocks on the command channel. While nothing is playing it
+    /// sleeps until the next command arrives, otherwise it also wakes up when ring buffer needs
+    /// refilling, play deadline of a queued sound runs out or it is time to reopen lost device.
+    /// Output stream wakes it up as soon as it fails, see [`SoundCommand::DeviceError`].
     fn run_event_loop(mut self) {
         loop {
-            let shutdown = self.process_incoming_commands();
+            let shutdown = self.wait_for_commands();
             if shutdown {
                 break;
             }
+            self.update_playback();
+        }
+        self.stop();
+    }
 
-            if let Some(current_sound) = self.current_sound.as_mut() {
-                let finished = current_sound.fill_buffer();
-                if finished {
-                    let _ = self.current_sound.take();
-                }
-            }
+    /// Save pending settings and complete output once event loop is over, then let requesters of
+    /// shutdown know that the system has stopped.
+    fn stop(&mut self) {
+        if self.save_at.is_some() {
+            self.save_state();
+        }
+        if let Some(event) = self.output.finish() {
+            self.events.notify_device(event);
+        }
+        for sender in self.shutdown_requesters.drain(..) {
+            let _ = sender.try_send(());
+        }
+    }
 
-            if let None = self.current_sound {
-                if let Some(next_sound) = self.next_sound() {
-                    self.current_sound = Some(
-                        Sound::play(next_sound.path.as_str(), &self.sink)
-                            .expect("Failed to play sound"),
-                    );
+    /// Check output backend, pause the main sink once output has faded out and drive playback of
+    /// every layer forward, see [`Layer::update()`].
+    fn update_playback(&mut self) {
+        let now = self.clock.now();
+        if let Some(event) = self.output.check(now) {
+            self.events.notify_device(event);
+        }
+        if self.pause_at.is_some_and(|pause_at| pause_at <= now) {
+            self.pause_at = None;
+            self.sink.pause();
+        }
+        if self.save_at.is_some_and(|save_at| save_at <= now) {
+            self.save_state();
+        }
+        for layer in &mut self.layers {
+            layer.update(now, &self.preemption, &mut self.events);
+        }
+        self.update_ducking();
+
+        if self.is_rendering() {
+            self.output.render();
+        }
+    }
+
+    /// Duck layers according to ducking rules and sounds playing right now. Layers which are not
+    /// ducked anymore are released using release time of the rule which ducked them last.
+    fn update_ducking(&mut self) {
+        if self.ducking.is_empty() {
+            return;
+        }
+        let ducking = self
+            .layers
+            .iter()
+            .zip(self.mixer.ducking())
+            .map(|(ducked, previous)| {
+                let applied = self
+                    .ducking
+                    .iter()
+                    .filter(|rule| {
+                        self.layers.iter().any(|playing| {
+                            playing
+                                .playing_priorities()
+                                .any(|priority| rule.applies(priority, &playing.name, &ducked.name))
+                        })
+                    })
+                    .max_by(|a, b| a.amount_db.total_cmp(&b.amount_db));
+                match applied {
+                    Some(rule) => Ducking {
+                        amount_db: rule.amount_db.max(0.0),
+                        attack: rule.attack,
+                        release: rule.release,
+                    },
+                    None => Ducking {
+                        amount_db: 0.0,
+                        ..previous
+                    },
                 }
-            }
+            })
+            .collect();
+        self.mixer.set_ducking(ducking);
+    }
+
+    /// Returns true if event loop has to render output of non real time backend, which is done
+    /// while a layer has something to play and the output is not paused.
+    fn is_rendering(&self) -> bool {
+        !self.output.is_realtime()
+            && !self.sink.is_paused()
+            && self.layers.iter().any(Layer::is_playing)
+    }
+
+    /// Returns index of the layer with given `name`, if any.
+    fn layer_index(&self, name: &str) -> Option<usize> {
+        self.layers.iter().position(|layer| *layer.name == *name)
+    }
+
+    /// Block until either a command arrives or the next wake-up returned by
+    /// [`OrbSoundSystem::next_wakeup()`] is due, then process all pending commands. Returns true if
+    /// system should shut down, false otherwise.
+    fn wait_for_commands(&mut self) -> bool {
+        let timeout = self
+            .next_wakeup()
+            .map(|wakeup| wakeup.saturating_duration_since(self.clock.now()));
+        let received = match timeout.into_iter().chain(self.clock.poll_interval()).min() {
+            Some(timeout) => self.command_receiver.recv_timeout(timeout),
+            None => self
+                .command_receiver
+                .recv()
+                .map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok(command) => self.process_command(command) || self.process_incoming_commands(),
+            Err(RecvTimeoutError::Timeout) => false,
+            Err(RecvTimeoutError::Disconnected) => true,
         }
     }
 
+    /// Returns the moment event loop has to wake up even if no command arrives. That is the
+    /// earliest of:
+    ///
+    /// - the moment a layer needs attention, see [`Layer::next_wakeup()`]
+    /// - next attempt to reopen lost output device
+    /// - the moment output has faded out and the main sink has to be paused
+    /// - the moment changed settings have to be saved
+    /// - right away, if output of non real time backend has to be rendered
+    ///
+    /// Returns `None` if there is nothing to wait for.
+    fn next_wakeup(&self) -> Option<Instant> {
+        let now = self.clock.now();
+        let paused = self.sink.is_paused();
+        self.layers
+            .iter()
+            .filter_map(|layer| layer.next_wakeup(now, paused))
+            .chain(self.output.next_check())
+            .chain(self.pause_at)
+            .chain(self.save_at)
+            .chain(self.is_rendering().then_some(now))
+            .min()
+    }
+
     /// Process commands coming from channel. Returns true if system should shut down, false
     /// otherwise.
     fn process_incoming_commands(&mut self) -> bool {
         loop {
             match self.command_receiver.try_recv() {
-                Ok(command) => match command {
-                    SoundCommand::PlaySound(command) => {
-                        self.queue.push_back(command);
-                    }
-                    SoundCommand::SetVolume(value) => {
-                        self.sink.set_volume(value);
-                    }
-                    SoundCommand::AdjustVolume(delta) => {
-                        self.sink.set_volume(self.sink.volume() + delta)
-                    }
-                    SoundCommand::Pause => {
-                        self.sink.pause();
-                    }
-                    SoundCommand::Resume => {
-                        self.sink.play();
-                    }
-                    SoundCommand::Shutdown => {
+                Ok(command) => {
+                    if self.process_command(command) {
                         return true;
                     }
-                },
-                Err(err) => {
-                    return match err {
-                        TryRecvError::Disconnected => true,
-                        _ => false,
-                    }
                 }
+                Err(err) => return matches!(err, TryRecvError::Disconnected),
             };
         }
     }
 
-    /// Returns next sound to be played by sorting queue and taking first sound. Checks play
-    /// deadlines and drops "expired" sounds
-    fn next_sound(&mut self) -> Option<PlaySoundCommand> {
-        self.queue.make_contiguous().sort();
-        while let Some(next) = self.queue.pop_front() {
-            match next.play_deadline {
-                Some(deadline) => {
-                    if Instant::now() <= deadline {
-                        return Some(next);
+    /// Process single command. Returns true if system should shut down, false otherwise.
+    fn process_command(&mut self, command: SoundCommand) -> bool {
+        let saved = matches!(
+            command,
+            SoundCommand::SetVolume(_)
+                | SoundCommand::SetVolumeDb(_)
+                | SoundCommand::AdjustVolume(_)
+                | SoundCommand::AdjustVolumeDb(_)
+                | SoundCommand::Mute
+                | SoundCommand::Unmute
+                | SoundCommand::SetLayerVolume(..)
+                | SoundCommand::PauseLayer(_)
+                | SoundCommand::ResumeLayer(_)
+        );
+        match command {
+            SoundCommand::PlaySound(command) => match self.layer_index(&command.layer) {
+                Some(index) => {
+                    self.events.notify(&command, PlaybackEvent::Queued);
+                    self.layers[index].queue.push_back(command);
+                }
+                None => {
+                    let err = OrbSoundSystemError::LayerNotFound(command.layer.to_string());
+                    self.events.notify(&command, PlaybackEvent::Failed(err));
+                }
+            },
+            SoundCommand::SetVolume(value) => {
+                self.volume.set_linear(value);
+                self.apply_volume();
+                self.events
+                    .publish(SoundEvent::VolumeChanged(self.volume.linear()));
+            }
+            SoundCommand::SetVolumeDb(db) => {
+                self.volume.set_db(db);
+                self.apply_volume();
+                self.events
+                    .publish(SoundEvent::VolumeChanged(self.volume.linear()));
+            }
+            SoundCommand::AdjustVolume(delta) => {
+                self.volume.adjust_linear(delta);
+                self.apply_volume();
+                self.events
+                    .publish(SoundEvent::VolumeChanged(self.volume.linear()));
+            }
+            SoundCommand::AdjustVolumeDb(delta_db) => {
+                self.volume.adjust(delta_db);
+                self.apply_volume();
+                self.events
+                    .publish(SoundEvent::VolumeChanged(self.volume.linear()));
+            }
+            SoundCommand::Mute => {
+                if self.volume.mute() {
+                    self.apply_volume();
+                    self.events.publish(SoundEvent::Muted);
+                }
+            }
+            SoundCommand::Unmute => {
+                if self.volume.unmute() {
+                    self.apply_volume();
+                    self.events.publish(SoundEvent::Unmuted);
+                }
+            }
+            SoundCommand::Pause => {
+                if !self.fader.is_paused() {
+                    self.fader.pause(self.fades.pause);
+                    if self.fades.pause.is_zero() {
+                        self.sink.pause();
+                    } else {
+                        self.pause_at = Some(self.clock.now() + self.fades.pause);
                     }
+                    self.events.publish(SoundEvent::Paused);
+                }
+            }
+            SoundCommand::Resume => {
+                if self.fader.is_paused() {
+                    self.pause_at = None;
+                    self.fader.resume(self.fades.resume);
+                    self.sink.play();
+                    self.events.publish(SoundEvent::Resumed);
+                }
+            }
+            SoundCommand::SetLayerVolume(name, value) => {
+                if let Some(index) = self.layer_index(&name) {
+                    let sink = &self.layers[index].sink;
+                    sink.set_volume(value);
+                    self.events
+                        .publish(SoundEvent::LayerVolumeChanged(name, sink.volume()));
+                }
+            }
+            SoundCommand::PauseLayer(name) => {
+                let sink = self.layer_index(&name).map(|i| &self.layers[i].sink);
+                if let Some(sink) = sink.filter(|sink| !sink.is_paused()) {
+                    sink.pause();
+                    self.events.publish(SoundEvent::LayerPaused(name));
+                }
+            }
+            SoundCommand::ResumeLayer(name) => {
+                let sink = self.layer_index(&name).map(|i| &self.layers[i].sink);
+                if let Some(sink) = sink.filter(|sink| sink.is_paused()) {
+                    sink.play();
+                    self.events.publish(SoundEvent::LayerResumed(name));
                 }
-                None => return Some(next),
             }
+            SoundCommand::Cancel(id) => {
+                for layer in &mut self.layers {
+                    layer.cancel(|request| request.id == id, &mut self.events);
+                }
+            }
+            SoundCommand::CancelPath(path) => {
+                for layer in &mut self.layers {
+                    layer.cancel(
+                        |request| matches!(&request.source, SoundSource::File(p) if *p == path),
+                        &mut self.events,
+                    );
+                }
+            }
+            SoundCommand::CancelPriority(name, priority) => {
+                if let Some(index) = self.layer_index(&name) {
+                    // lower priorities are greater in terms of ordering
+                    self.layers[index]
+                        .cancel(|request| request.priority >= priority, &mut self.events);
+                }
+            }
+            SoundCommand::ClearQueue(name) => {
+                if let Some(index) = self.layer_index(&name) {
+                    self.layers[index].remove_queued(
+                        |_| true,
+                        || PlaybackEvent::Cancelled,
+                        &mut self.events,
+                    );
+                }
+            }
+            SoundCommand::Stop(name) => {
+                if let Some(index) = self.layer_index(&name) {
+                    self.layers[index].stop_current(&mut self.events);
+                }
+            }
+            SoundCommand::Status(sender) => {
+                // requester may have given up waiting
+                let _ = sender.try_send(self.status());
+            }
+            SoundCommand::Subscribe(sender) => {
+                self.events.subscribe(sender);
+            }
+            SoundCommand::SubscribeDeviceEvents(sender) => {
+                self.events.subscribe_device(sender);
+            }
+            SoundCommand::DeviceError => {
+                // failure is picked up by the output backend check which follows the commands
+            }
+            SoundCommand::Shutdown(sender) => {
+                self.shutdown_requesters.push(sender);
+                return true;
+            }
+        }
+        if saved {
+            self.schedule_save();
+        }
+        false
+    }
+
+    /// Ramp gain applied to sounds to the volume of the whole output.
+    fn apply_volume(&self) {
+        self.fader.set_volume(self.volume.gain(), self.fades.volume);
+    }
+
+    /// Apply volume and layer settings saved before restart. Settings of layers which are not
+    /// configured anymore are dropped.
+    fn restore_state(&mut self, state: SavedState) {
+        if let Some(volume_db) = state.volume_db {
+            self.volume.set_db(volume_db);
+        }
+        if state.muted {
+            self.volume.mute();
+        }
+        self.fader.set_volume(self.volume.gain(), Duration::ZERO);
+        for saved in state.layers {
+            if let Some(index) = self.layer_index(&saved.name) {
+                let sink = &self.layers[index].sink;
+                sink.set_volume(saved.volume);
+                if saved.paused {
+                    sink.pause();
+                }
+            }
+        }
+    }
+
+    /// Plan saving of changed settings to the state file, if any. Settings are written at most
+    /// once per [`state::SAVE_DELAY`], so that a burst of volume changes touches storage once.
+    fn schedule_save(&mut self) {
+        if self.state_file.is_none() {
+            return;
+        }
+        if self.current_state() == self.saved_state {
+            self.save_at = None;
+        } else if self.save_at.is_none() {
+            self.save_at = Some(self.clock.now() + state::SAVE_DELAY);
+        }
+    }
+
+    /// Write current volume and layer settings to the state file, if any.
+    fn save_state(&mut self) {
+        self.save_at = None;
+        let state_file = match &self.state_file {
+            Some(state_file) => state_file,
+            None => return,
+        };
+        let state = self.current_state();
+        // Failing to save settings must not stop playback, they are saved again on next change
+        if state_file.save(&state).is_ok() {
+            self.saved_state = state;
+        }
+    }
+
+    /// Returns volume and layer settings kept in the state file.
+    fn current_state(&self) -> SavedState {
+        SavedState {
+            volume_db: Some(self.volume.db()),
+            muted: self.volume.is_muted(),
+            layers: self
+                .layers
+                .iter()
+                .map(|layer| SavedLayer {
+                    name: layer.name.to_string(),
+                    volume: layer.sink.volume(),
+                    paused: layer.sink.is_paused(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns snapshot of the system state.
+    fn status(&self) -> SoundSystemStatus {
+        let now = self.clock.now();
+        let layers: Vec<_> = self.layers.iter().map(|layer| layer.status(now)).collect();
+        let default = &layers[0];
+        SoundSystemStatus {
+            current: default.current.clone(),
+            volume: self.volume.linear(),
+            volume_db: self.volume.db(),
+            muted: self.volume.is_muted(),
+            paused: self.fader.is_paused(),
+            null_output: self.null_output,
+            limiter: self.limiter.as_ref().map(|stats| stats.status()),
+            queue: default.queue.clone(),
+            layers,
         }
-        None
     }
 }
 
 #[cfg(test)]
+// Some of the tests decode WAV files
+#[cfg_attr(not(feature = "wav"), allow(unused_imports, dead_code))]
 mod test {
-    use std::collections::VecDeque;
-    use std::sync::mpsc;
     use std::sync::mpsc::Sender;
+    use std::sync::{mpsc, Arc, Weak};
     use std::time::{Duration, Instant};
 
-    use rodio::{OutputStream, Sink};
+    use rodio::buffer::SamplesBuffer;
+    use rodio::Sink;
 
-    use crate::handle::{PlaySoundCommand, SoundCommand, SoundPriority};
-    use crate::system::OrbSoundSystem;
+    use crate::clock::{Clock, ManualClock, SystemClock};
+    use crate::handle::{
+        PlaySoundCommand, PlaybackEvent, PlaybackId, Repeat, SoundCommand, SoundEvent,
+        SoundPriority, SoundSource, DEFAULT_LAYER,
+    };
+    use crate::system::events::EventBus;
+    use crate::system::fade::Fader;
+    use crate::system::layer::{FinishingSound, Layer};
+    use crate::system::mixer::Mixer;
+    use crate::system::sound::{Playback, Sound};
+    use crate::system::state::{self, SavedState, StateFile};
+    use crate::system::volume::Volume;
+    use crate::system::{
+        sound, DuckingRule, DuckingTrigger, Fades, LimiterSettings, OrbSoundSystem, OutputBackend,
+        PreemptedSound, PreemptionMode, PreemptionPolicy, RenderBuffer, RenderOutput, VolumeLimits,
+    };
+    use crate::OrbSoundSystemError;
 
     #[test]
     fn process_commands() {
@@ -170,20 +659,82 @@ mod test {
         let _ = system.process_incoming_commands();
         assert!(!system.sink.is_paused());
         // set volume
-        command_sender.send(SoundCommand::SetVolume(2.0)).unwrap();
+        command_sender.send(SoundCommand::SetVolume(0.5)).unwrap();
+        let _ = system.process_incoming_commands();
+        assert!((system.fader.volume() - 0.5).abs() < 0.001);
+        // volume is limited
+        command_sender
+            .send(SoundCommand::SetVolumeDb(20.0))
+            .unwrap();
         let _ = system.process_incoming_commands();
-        assert_eq!(system.sink.volume(), 2.0);
+        assert_eq!(system.volume.db(), 6.0);
         // adjust volume
+        command_sender
+            .send(SoundCommand::AdjustVolumeDb(-12.0))
+            .unwrap();
+        let _ = system.process_incoming_commands();
+        assert_eq!(system.volume.db(), -6.0);
+        command_sender
+            .send(SoundCommand::AdjustVolumeDb(12.0))
+            .unwrap();
+        let _ = system.process_incoming_commands();
+        assert_eq!(system.volume.db(), 0.0);
+        assert_eq!(system.fader.volume(), 1.0);
+        command_sender
+            .send(SoundCommand::AdjustVolume(-0.5))
+            .unwrap();
+        let _ = system.process_incoming_commands();
+        assert!((system.fader.volume() - 0.5).abs() < 0.001);
         command_sender
             .send(SoundCommand::AdjustVolume(0.5))
             .unwrap();
         let _ = system.process_incoming_commands();
-        assert_eq!(system.sink.volume(), 2.5);
+        assert!((system.fader.volume() - 1.0).abs() < 0.001);
+        // mute
+        command_sender.send(SoundCommand::Mute).unwrap();
+        let _ = system.process_incoming_commands();
+        assert_eq!(system.fader.volume(), 0.0);
+        assert!(system.status().muted);
+        command_sender.send(SoundCommand::Unmute).unwrap();
+        let _ = system.process_incoming_commands();
+        assert_eq!(system.fader.volume(), 1.0);
+        // device error only wakes event loop up
+        command_sender.send(SoundCommand::DeviceError).unwrap();
+        assert!(!system.process_incoming_commands());
+    }
+
+    #[test]
+    #[cfg(feature = "wav")]
+    fn subscribe() {
+        let (mut system, command_sender) = mock_system();
+        let (sender, events) = mpsc::sync_channel(10);
+        command_sender
+            .send(SoundCommand::Subscribe(sender.into()))
+            .unwrap();
+        command_sender.send(SoundCommand::Pause).unwrap();
+        command_sender.send(SoundCommand::Pause).unwrap();
+        command_sender.send(SoundCommand::SetVolume(0.5)).unwrap();
+        command_sender.send(SoundCommand::Resume).unwrap();
         command_sender
-            .send(SoundCommand::AdjustVolume(-1.0))
+            .send(SoundCommand::PlaySound(PlaySoundCommand {
+                id: PlaybackId(1),
+                ..PlaySoundCommand::mock("sounds/test.wav", SoundPriority::Default, None)
+            }))
             .unwrap();
         let _ = system.process_incoming_commands();
-        assert_eq!(system.sink.volume(), 1.5);
+        system.update_playback();
+
+        // pausing twice is reported once
+        assert!(matches!(events.try_recv(), Ok(SoundEvent::Paused)));
+        assert!(matches!(events.try_recv(), Ok(SoundEvent::VolumeChanged(v)) if v == 0.5));
+        assert!(matches!(events.try_recv(), Ok(SoundEvent::Resumed)));
+        assert!(
+            matches!(events.try_recv(), Ok(SoundEvent::Queued(info)) if info.id == PlaybackId(1))
+        );
+        assert!(
+            matches!(events.try_recv(), Ok(SoundEvent::Started(info)) if info.id == PlaybackId(1))
+        );
+        assert!(events.try_recv().is_err());
     }
 
     #[test]
@@ -197,38 +748,771 @@ mod test {
     #[test]
     fn next_sound() {
         let (mut system, command_sender) = mock_system();
-        let cmd = PlaySoundCommand {
-            path: "sounds/test.wav".to_string(),
-            priority: SoundPriority::Default,
-            play_deadline: None,
-        };
+        let cmd = PlaySoundCommand::mock("sounds/test.wav", SoundPriority::Default, None);
 
         command_sender.send(SoundCommand::PlaySound(cmd)).unwrap();
         let _ = system.process_incoming_commands();
-        assert!(system.next_sound().is_some());
+        assert!(system.layers[0]
+            .next_sound(Instant::now(), &mut system.events)
+            .is_some());
     }
 
     #[test]
     fn next_sound_after_deadline() {
         let (mut system, _command_sender) = mock_system();
-        system.queue.push_back(PlaySoundCommand {
-            path: "sounds/test.wav".to_string(),
-            priority: SoundPriority::Default,
-            play_deadline: Some(Instant::now() - Duration::from_millis(100)),
+        system.layers[0].queue.push_back(PlaySoundCommand::mock(
+            "sounds/test.wav",
+            SoundPriority::Default,
+            Some(Instant::now() - Duration::from_millis(100)),
+        ));
+
+        assert!(system.layers[0]
+            .next_sound(Instant::now(), &mut system.events)
+            .is_none());
+    }
+
+    #[test]
+    fn next_wakeup() {
+        let (mut system, _command_sender) = mock_system();
+        assert!(system.next_wakeup().is_none());
+        let deadline = Instant::now() + Duration::from_secs(1);
+        system.layers[0].queue.push_back(PlaySoundCommand::mock(
+            "sounds/test.wav",
+            SoundPriority::Default,
+            None,
+        ));
+        assert!(system.next_wakeup().is_none());
+        system.layers[0].queue.push_back(PlaySoundCommand::mock(
+            "sounds/test.wav",
+            SoundPriority::Default,
+            Some(deadline),
+        ));
+        assert_eq!(system.next_wakeup(), Some(deadline));
+    }
+
+    #[test]
+    fn tail_poll_backoff() {
+        let (mut layer, _output) = Layer::new(
+            DEFAULT_LAYER.into(),
+            Fades::default(),
+            None,
+            Arc::new(Fader::new(1.0)),
+        );
+        let reader = Box::new(SamplesBuffer::new(1, 1000, vec![1i16; 10]));
+        let sound = Sound::resume(
+            Playback::Once(reader),
+            &layer.sink,
+            1.0,
+            Duration::ZERO,
+            &Arc::new(Fader::new(1.0)),
+        );
+        let now = Instant::now();
+        layer.finishing_sounds.push(FinishingSound {
+            request: PlaySoundCommand::mock("sounds/test.wav", SoundPriority::Default, None),
+            tail: sound.into_tail(now),
+        });
+        // 10 samples of mono 1kHz sound end in 10ms
+        assert_eq!(
+            layer.next_wakeup(now, false),
+            Some(now + Duration::from_millis(10))
+        );
+        let late = now + Duration::from_millis(12);
+        assert_eq!(
+            layer.next_wakeup(late, false),
+            Some(late + Duration::from_millis(5))
+        );
+        // sink never plays the tail, checks get less frequent
+        let later = now + Duration::from_millis(210);
+        assert_eq!(
+            layer.next_wakeup(later, false),
+            Some(later + Duration::from_millis(100))
+        );
+        let stalled = now + Duration::from_secs(10);
+        assert_eq!(
+            layer.next_wakeup(stalled, false),
+            Some(stalled + Duration::from_millis(500))
+        );
+    }
+
+    #[test]
+    fn drop_expired() {
+        let (mut system, _command_sender) = mock_system();
+        system.layers[0].queue.push_back(PlaySoundCommand::mock(
+            "sounds/test.wav",
+            SoundPriority::Default,
+            Some(Instant::now() - Duration::from_millis(100)),
+        ));
+        system.layers[0].queue.push_back(PlaySoundCommand::mock(
+            "sounds/test.wav",
+            SoundPriority::Default,
+            Some(Instant::now() + Duration::from_secs(1)),
+        ));
+        system.layers[0].drop_expired(system.clock.now(), &mut system.events);
+        assert_eq!(system.layers[0].queue.len(), 1);
+    }
+
+    #[test]
+    fn drop_expired_with_manual_clock() {
+        let (mut system, _command_sender) = mock_system();
+        let clock = Arc::new(ManualClock::new());
+        system.clock = clock.clone();
+        system.layers[0].queue.push_back(PlaySoundCommand::mock(
+            "sounds/test.wav",
+            SoundPriority::Default,
+            Some(clock.now() + Duration::from_secs(1)),
+        ));
+        system.layers[0].drop_expired(system.clock.now(), &mut system.events);
+        assert_eq!(system.layers[0].queue.len(), 1);
+        assert_eq!(
+            system.next_wakeup(),
+            Some(clock.now() + Duration::from_secs(1))
+        );
+        // deadline is inclusive
+        clock.advance(Duration::from_secs(1));
+        system.layers[0].drop_expired(system.clock.now(), &mut system.events);
+        assert_eq!(system.layers[0].queue.len(), 1);
+        clock.advance(Duration::from_millis(1));
+        system.layers[0].drop_expired(system.clock.now(), &mut system.events);
+        assert!(system.layers[0].queue.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "wav")]
+    fn status() {
+        let (mut system, command_sender) = mock_system();
+        let clock = Arc::new(ManualClock::new());
+        system.clock = clock.clone();
+        let status = system.status();
+        assert!(status.current.is_none());
+        assert!(status.queue.is_empty());
+        assert_eq!(status.volume, 1.0);
+        assert!(!status.null_output);
+        assert!(!status.paused);
+
+        let playing = PlaySoundCommand {
+            id: PlaybackId(1),
+            ..PlaySoundCommand::mock("sounds/test.wav", SoundPriority::Default, None)
+        };
+        let queued = [
+            PlaySoundCommand {
+                id: PlaybackId(2),
+                ..PlaySoundCommand::mock("sounds/test.wav", SoundPriority::Default, None)
+            },
+            PlaySoundCommand {
+                id: PlaybackId(3),
+                ..PlaySoundCommand::mock(
+                    "sounds/test.wav",
+                    SoundPriority::High,
+                    Some(clock.now() + Duration::from_secs(2)),
+                )
+            },
+        ];
+        system.layers[0].queue.push_back(playing);
+        system.update_playback();
+        system.layers[0].queue.extend(queued);
+        command_sender.send(SoundCommand::Pause).unwrap();
+        command_sender.send(SoundCommand::SetVolume(0.5)).unwrap();
+        let _ = system.process_incoming_commands();
+        clock.advance(Duration::from_secs(1));
+
+        let status = system.status();
+        let current = status.current.unwrap();
+        assert_eq!(current.id, PlaybackId(1));
+        assert_eq!(current.priority, SoundPriority::Default);
+        assert_eq!(status.volume, 0.5);
+        assert!(status.paused);
+        // queue is reported in playing order
+        assert_eq!(status.queue.len(), 2);
+        assert_eq!(status.queue[0].id, PlaybackId(3));
+        assert_eq!(status.queue[0].remaining, Some(Duration::from_secs(1)));
+        assert_eq!(status.queue[1].id, PlaybackId(2));
+        assert_eq!(status.queue[1].remaining, None);
+    }
+
+    #[test]
+    #[cfg(feature = "wav")]
+    fn playback_events() {
+        let (mut system, command_sender) = mock_system();
+        let (sink, mut output) = Sink::new_idle();
+        system.layers[0].sink = sink;
+        let (event_sender, events) = mpsc::channel();
+        let expired = PlaySoundCommand {
+            event_sender: event_sender.clone().into(),
+            ..PlaySoundCommand::mock(
+                "sounds/test.wav",
+                SoundPriority::Default,
+                Some(Instant::now() - Duration::from_millis(100)),
+            )
+        };
+        let failed = PlaySoundCommand {
+            event_sender: event_sender.clone().into(),
+            ..PlaySoundCommand::mock("sounds/missing.wav", SoundPriority::Urgent, None)
+        };
+        let played = PlaySoundCommand {
+            event_sender: event_sender.into(),
+            ..PlaySoundCommand::mock("sounds/test.wav", SoundPriority::High, None)
+        };
+        for command in [expired, failed, played] {
+            command_sender
+                .send(SoundCommand::PlaySound(command))
+                .unwrap();
+        }
+        let _ = system.process_incoming_commands();
+        for _ in 0..3 {
+            assert!(matches!(events.try_recv(), Ok(PlaybackEvent::Queued)));
+        }
+
+        system.update_playback();
+        assert!(matches!(events.try_recv(), Ok(PlaybackEvent::Expired)));
+        assert!(matches!(
+            events.try_recv(),
+            Ok(PlaybackEvent::Failed(OrbSoundSystemError::SoundFileErr(_)))
+        ));
+        assert!(matches!(events.try_recv(), Ok(PlaybackEvent::Started)));
+
+        // play the sound out by pulling samples from the sink
+        while system.layers[0].current_sound.is_some()
+            || !system.layers[0].finishing_sounds.is_empty()
+        {
+            assert!(events.try_recv().is_err());
+            output.by_ref().take(1000).for_each(drop);
+            system.update_playback();
+        }
+        assert!(matches!(events.try_recv(), Ok(PlaybackEvent::Finished)));
+        // all senders are dropped after final event
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "wav")]
+    fn cancel() {
+        let (mut system, command_sender) = mock_system();
+        let (event_sender, events) = mpsc::channel();
+        let requests = [
+            (1, "sounds/test.wav", SoundPriority::Urgent),
+            (2, "sounds/other.wav", SoundPriority::High),
+            (3, "sounds/test.wav", SoundPriority::High),
+            (4, "sounds/test.wav", SoundPriority::Default),
+            (5, "sounds/other.wav", SoundPriority::Urgent),
+        ];
+        for (id, path, priority) in requests {
+            system.layers[0].queue.push_back(PlaySoundCommand {
+                id: PlaybackId(id),
+                event_sender: event_sender.clone().into(),
+                ..PlaySoundCommand::mock(path, priority, None)
+            });
+        }
+        let queued_ids = |system: &OrbSoundSystem| {
+            system.layers[0]
+                .queue
+                .iter()
+                .map(|command| command.id)
+                .collect::<Vec<_>>()
+        };
+
+        command_sender
+            .send(SoundCommand::CancelPriority(
+                DEFAULT_LAYER.into(),
+                SoundPriority::High,
+            ))
+            .unwrap();
+        let _ = system.process_incoming_commands();
+        assert_eq!(queued_ids(&system), vec![PlaybackId(1), PlaybackId(5)]);
+        command_sender
+            .send(SoundCommand::Cancel(PlaybackId(5)))
+            .unwrap();
+        let _ = system.process_incoming_commands();
+        assert_eq!(queued_ids(&system), vec![PlaybackId(1)]);
+        for _ in 0..4 {
+            assert!(matches!(events.try_recv(), Ok(PlaybackEvent::Cancelled)));
+        }
+
+        // cancelling by path stops currently playing sound
+        system.update_playback();
+        assert!(matches!(events.try_recv(), Ok(PlaybackEvent::Started)));
+        command_sender
+            .send(SoundCommand::CancelPath("sounds/test.wav".to_string()))
+            .unwrap();
+        let _ = system.process_incoming_commands();
+        assert!(system.layers[0].current_sound.is_none());
+        assert!(matches!(events.try_recv(), Ok(PlaybackEvent::Cancelled)));
+    }
+
+    #[test]
+    #[cfg(feature = "wav")]
+    fn clear_queue_and_stop() {
+        let (mut system, command_sender) = mock_system();
+        for _ in 0..3 {
+            system.layers[0].queue.push_back(PlaySoundCommand::mock(
+                "sounds/test.wav",
+                SoundPriority::Default,
+                None,
+            ));
+        }
+        system.update_playback();
+        assert!(system.layers[0].current_sound.is_some());
+        command_sender
+            .send(SoundCommand::Stop(DEFAULT_LAYER.into()))
+            .unwrap();
+        let _ = system.process_incoming_commands();
+        assert!(system.layers[0].current_sound.is_none());
+        assert_eq!(system.layers[0].queue.len(), 2);
+        system.update_playback();
+        command_sender
+            .send(SoundCommand::ClearQueue(DEFAULT_LAYER.into()))
+            .unwrap();
+        let _ = system.process_incoming_commands();
+        assert!(system.layers[0].current_sound.is_some());
+        assert!(system.layers[0].queue.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "wav")]
+    fn preemption() {
+        let (mut system, _command_sender) = mock_system();
+        let (sink, mut output) = Sink::new_idle();
+        system.layers[0].sink = sink;
+        let jingle = |id| PlaySoundCommand {
+            id: PlaybackId(id),
+            ..PlaySoundCommand::mock("sounds/test.wav", SoundPriority::Default, None)
+        };
+        let urgent = |id| PlaySoundCommand {
+            id: PlaybackId(id),
+            ..PlaySoundCommand::mock("sounds/test.wav", SoundPriority::Urgent, None)
+        };
+        let current_id =
+            |system: &OrbSoundSystem| system.layers[0].current_sound.as_ref().unwrap().request.id;
+
+        // never
+        system.layers[0].queue.push_back(jingle(1));
+        system.update_playback();
+        system.layers[0].queue.push_back(urgent(2));
+        system.update_playback();
+        assert_eq!(current_id(&system), PlaybackId(1));
+
+        // interrupt and resume
+        system.preemption = PreemptionPolicy {
+            mode: PreemptionMode::Interrupt,
+            preempted: PreemptedSound::Resume,
+        };
+        system.update_playback();
+        assert_eq!(current_id(&system), PlaybackId(2));
+        assert!(system.layers[0]
+            .suspended_sounds
+            .contains_key(&PlaybackId(1)));
+        system.layers[0].stop_current(&mut system.events);
+        system.update_playback();
+        assert_eq!(current_id(&system), PlaybackId(1));
+        assert!(system.layers[0].suspended_sounds.is_empty());
+
+        // fade out and drop
+        system.preemption = PreemptionPolicy {
+            mode: PreemptionMode::FadeOut(Duration::from_millis(10)),
+            preempted: PreemptedSound::Drop,
+        };
+        system.layers[0].queue.push_back(urgent(3));
+        system.update_playback();
+        assert_eq!(current_id(&system), PlaybackId(1));
+        assert!(system.layers[0].current_sound.as_ref().unwrap().preempted);
+        // let the sink consume faded samples
+        while current_id(&system) == PlaybackId(1) {
+            output.by_ref().take(100).for_each(drop);
+            system.update_playback();
+        }
+        assert_eq!(current_id(&system), PlaybackId(3));
+        assert!(system.layers[0].queue.is_empty());
+
+        // restarted sound is played even once its deadline has passed
+        system.preemption.preempted = PreemptedSound::Restart;
+        system.preemption.mode = PreemptionMode::Interrupt;
+        system.layers[0].stop_current(&mut system.events);
+        system.layers[0].queue.push_back(PlaySoundCommand {
+            play_deadline: Some(Instant::now() + Duration::from_millis(10)),
+            ..jingle(4)
         });
+        system.update_playback();
+        system.layers[0].queue.push_back(urgent(5));
+        system.update_playback();
+        assert_eq!(current_id(&system), PlaybackId(5));
+        assert_eq!(system.layers[0].queue.len(), 1);
+        std::thread::sleep(Duration::from_millis(20));
+        system.layers[0].stop_current(&mut system.events);
+        system.update_playback();
+        assert_eq!(current_id(&system), PlaybackId(4));
+    }
 
-        assert!(system.next_sound().is_none());
+    #[test]
+    #[cfg(feature = "wav")]
+    fn layers() {
+        let (mut system, command_sender, _) =
+            mock_layered_system(&[DEFAULT_LAYER, "ambient"], Fades::default());
+        let (sender, events) = mpsc::sync_channel(10);
+        command_sender
+            .send(SoundCommand::Subscribe(sender.into()))
+            .unwrap();
+        let (event_sender, playback_events) = mpsc::channel();
+        let request = |id, layer: &str| PlaySoundCommand {
+            id: PlaybackId(id),
+            layer: layer.into(),
+            event_sender: event_sender.clone().into(),
+            ..PlaySoundCommand::mock("sounds/test.wav", SoundPriority::Default, None)
+        };
+        for command in [request(1, DEFAULT_LAYER), request(2, "ambient")] {
+            command_sender
+                .send(SoundCommand::PlaySound(command))
+                .unwrap();
+        }
+        let _ = system.process_incoming_commands();
+        system.update_playback();
+        // sounds of different layers play simultaneously
+        let current_id = |layer: &Layer| layer.current_sound.as_ref().map(|c| c.request.id);
+        assert_eq!(current_id(&system.layers[0]), Some(PlaybackId(1)));
+        assert_eq!(current_id(&system.layers[1]), Some(PlaybackId(2)));
+        assert!(
+            matches!(events.try_recv(), Ok(SoundEvent::Queued(info)) if &*info.layer == DEFAULT_LAYER)
+        );
+        assert!(
+            matches!(events.try_recv(), Ok(SoundEvent::Queued(info)) if &*info.layer == "ambient")
+        );
+
+        for command in [
+            SoundCommand::PauseLayer("ambient".into()),
+            SoundCommand::SetLayerVolume("ambient".into(), 0.5),
+            SoundCommand::Stop(DEFAULT_LAYER.into()),
+        ] {
+            command_sender.send(command).unwrap();
+        }
+        let _ = system.process_incoming_commands();
+        assert!(system.layers[1].sink.is_paused());
+        assert!(!system.layers[0].sink.is_paused());
+        assert_eq!(system.layers[1].sink.volume(), 0.5);
+        // stop applies to given layer only
+        assert_eq!(current_id(&system.layers[0]), None);
+        assert_eq!(current_id(&system.layers[1]), Some(PlaybackId(2)));
+
+        let status = system.status();
+        assert!(status.current.is_none());
+        assert_eq!(status.layers.len(), 2);
+        assert_eq!(&*status.layers[1].name, "ambient");
+        assert!(status.layers[1].paused);
+        assert_eq!(status.layers[1].current.as_ref().unwrap().id, PlaybackId(2));
+
+        // requests of unknown layers fail
+        command_sender
+            .send(SoundCommand::PlaySound(request(3, "missing")))
+            .unwrap();
+        let _ = system.process_incoming_commands();
+        let final_events: Vec<_> = playback_events
+            .try_iter()
+            .filter(|e| e.is_final())
+            .collect();
+        assert!(matches!(
+            final_events.as_slice(),
+            [
+                PlaybackEvent::Cancelled,
+                PlaybackEvent::Failed(OrbSoundSystemError::LayerNotFound(_))
+            ]
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "wav")]
+    fn ducking() {
+        let (mut system, _command_sender, _) =
+            mock_layered_system(&[DEFAULT_LAYER, "ambient"], Fades::default());
+        system.ducking = vec![
+            DuckingRule::new(DuckingTrigger::Priority(SoundPriority::High), 6.0),
+            DuckingRule::new(DuckingTrigger::Priority(SoundPriority::Urgent), 12.0)
+                .layers(["ambient"]),
+        ];
+        let amounts = |system: &OrbSoundSystem| {
+            system
+                .mixer
+                .ducking()
+                .iter()
+                .map(|ducking| ducking.amount_db)
+                .collect::<Vec<_>>()
+        };
+        system.layers[1].queue.push_back(PlaySoundCommand {
+            layer: "ambient".into(),
+            ..PlaySoundCommand::mock("sounds/test.wav", SoundPriority::Default, None)
+        });
+        system.update_playback();
+        assert_eq!(amounts(&system), vec![0.0, 0.0]);
+
+        system.layers[0].queue.push_back(PlaySoundCommand::mock(
+            "sounds/test.wav",
+            SoundPriority::Urgent,
+            None,
+        ));
+        system.update_playback();
+        // the rule ducking the most wins
+        assert_eq!(amounts(&system), vec![0.0, 12.0]);
+
+        // paused layer is not heard, so it does not duck others
+        system.layers[0].sink.pause();
+        system.update_playback();
+        assert_eq!(amounts(&system), vec![0.0, 0.0]);
+        assert_eq!(system.mixer.ducking()[1].release, system.ducking[1].release);
+    }
+
+    #[test]
+    #[cfg(feature = "wav")]
+    fn fades() {
+        let fades = Fades {
+            stop: Duration::from_millis(100),
+            pause: Duration::from_millis(100),
+            ..Fades::default()
+        };
+        let (mut system, command_sender, _) = mock_layered_system(&[DEFAULT_LAYER], fades);
+        let clock = Arc::new(ManualClock::new());
+        system.clock = clock.clone();
+        let (sender, events) = mpsc::channel();
+        for _ in 0..2 {
+            system.layers[0].queue.push_back(PlaySoundCommand {
+                event_sender: sender.clone().into(),
+                ..PlaySoundCommand::mock("sounds/test.wav", SoundPriority::Default, None)
+            });
+        }
+        system.update_playback();
+        assert!(matches!(events.try_recv(), Ok(PlaybackEvent::Started)));
+
+        // stopped sound is cancelled right away, but stays current until it fades out
+        command_sender
+            .send(SoundCommand::Stop(DEFAULT_LAYER.into()))
+            .unwrap();
+        let _ = system.process_incoming_commands();
+        assert!(matches!(events.try_recv(), Ok(PlaybackEvent::Cancelled)));
+        assert!(system.layers[0].current_sound.as_ref().unwrap().stopping);
+        for _ in 0..100 {
+            system.update_playback();
+            if events.try_recv().is_ok() {
+                break;
+            }
+        }
+        // next sound has started once previous one faded out
+        let current = system.layers[0].current_sound.as_ref().unwrap();
+        assert!(!current.stopping);
+        assert!(events.try_recv().is_err());
+
+        // output is reported paused right away, main sink is paused once output fades out
+        command_sender.send(SoundCommand::Pause).unwrap();
+        let _ = system.process_incoming_commands();
+        assert!(system.status().paused);
+        assert!(!system.sink.is_paused());
+        clock.advance(Duration::from_millis(100));
+        system.update_playback();
+        assert!(system.sink.is_paused());
+    }
+
+    #[test]
+    fn state_file() {
+        let path = std::env::temp_dir().join("orb_sound_system_state_test");
+        let _ = std::fs::remove_file(&path);
+        let clock = Arc::new(ManualClock::new());
+        let start = || {
+            let (tx, rx) = mpsc::channel();
+            let builder = OrbSoundSystem::builder()
+                .layer("ambient")
+                .state_file(&path)
+                .output(RenderOutput::memory(2, 44100).0)
+                .clock(clock.clone());
+            (OrbSoundSystem::init(rx, Weak::new(), builder).unwrap(), tx)
+        };
+        let (mut system, command_sender) = start();
+        // nothing is saved if settings do not change
+        command_sender
+            .send(SoundCommand::PauseLayer("missing".into()))
+            .unwrap();
+        let _ = system.process_incoming_commands();
+        assert_eq!(system.save_at, None);
+
+        for command in [
+            SoundCommand::SetVolumeDb(-6.0),
+            SoundCommand::Mute,
+            SoundCommand::SetLayerVolume("ambient".into(), 0.5),
+            SoundCommand::PauseLayer("ambient".into()),
+        ] {
+            command_sender.send(command).unwrap();
+        }
+        let _ = system.process_incoming_commands();
+        // changes are saved at once after a delay
+        assert!(!path.exists());
+        assert_eq!(system.next_wakeup(), Some(clock.now() + state::SAVE_DELAY));
+        clock.advance(state::SAVE_DELAY);
+        system.update_playback();
+        assert!(path.exists());
+        assert_eq!(system.save_at, None);
+
+        // settings are restored by a system started later
+        let (restarted, command_sender) = start();
+        let status = restarted.status();
+        assert_eq!(status.volume_db, -6.0);
+        assert!(status.muted);
+        assert_eq!(restarted.fader.volume(), 0.0);
+        assert_eq!(status.layers[1].volume, 0.5);
+        assert!(status.layers[1].paused);
+        assert!(!status.layers[0].paused);
+
+        // pending changes are saved on shutdown
+        command_sender.send(SoundCommand::Unmute).unwrap();
+        command_sender
+            .send(SoundCommand::Shutdown(mpsc::channel().0.into()))
+            .unwrap();
+        restarted.run_event_loop();
+        assert!(!StateFile::new(&path).load().muted);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "wav")]
+    fn limiter() {
+        let (output, rendered) = RenderOutput::memory(2, 44100);
+        let builder = OrbSoundSystem::builder()
+            .output(output)
+            .limiter(LimiterSettings::default());
+        let (_command_sender, rx) = mpsc::channel();
+        let mut system = OrbSoundSystem::init(rx, Weak::new(), builder).unwrap();
+        assert_eq!(system.status().limiter.unwrap().engaged, 0);
+        let (event_sender, events) = mpsc::channel();
+        system.layers[0].queue.push_back(PlaySoundCommand {
+            gain: 8.0,
+            event_sender: event_sender.into(),
+            ..PlaySoundCommand::mock("sounds/test.wav", SoundPriority::Default, None)
+        });
+        while !matches!(events.try_recv(), Ok(PlaybackEvent::Finished)) {
+            system.update_playback();
+        }
+
+        // loud sound is kept below the ceiling of -1dB
+        let status = system.status().limiter.unwrap();
+        assert!(status.engaged > 0);
+        assert!(status.limited > Duration::ZERO);
+        let ceiling = (0.891 * i16::MAX as f32) as i16;
+        assert!(rendered
+            .samples()
+            .iter()
+            .all(|sample| sample.abs() <= ceiling));
+        assert!(rendered
+            .samples()
+            .iter()
+            .any(|sample| sample.abs() > ceiling / 2));
+    }
+
+    #[test]
+    #[cfg(feature = "wav")]
+    fn render() {
+        let (mut system, _command_sender, rendered) = mock_rendering_system();
+        let (event_sender, events) = mpsc::channel();
+        system.layers[0].queue.push_back(PlaySoundCommand {
+            event_sender: event_sender.into(),
+            ..PlaySoundCommand::mock("sounds/test.wav", SoundPriority::Default, None)
+        });
+        system.update_playback();
+        assert!(!rendered.is_empty());
+
+        // nothing is rendered while paused
+        system.sink.pause();
+        let len = rendered.len();
+        assert!(system.next_wakeup().is_none());
+        system.update_playback();
+        assert_eq!(rendered.len(), len);
+        system.sink.play();
+
+        while !matches!(events.try_recv(), Ok(PlaybackEvent::Finished)) {
+            system.update_playback();
+        }
+        // output has the same format as the file, so samples are the same up to rounding
+        let expected: Vec<i16> = sound::open(&SoundSource::File("sounds/test.wav".to_string()))
+            .unwrap()
+            .collect();
+        let rendered = rendered.samples();
+        assert!(rendered.len() >= expected.len());
+        assert!(expected
+            .iter()
+            .zip(&rendered)
+            .all(|(expected, rendered)| (expected - rendered).abs() <= 1));
+        assert!(rendered[expected.len()..].iter().all(|&s| s == 0));
+        assert!(system.next_wakeup().is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "wav")]
+    fn repeat() {
+        let (mut system, command_sender, rendered) = mock_rendering_system();
+        let (event_sender, events) = mpsc::channel();
+        let len = sound::open(&SoundSource::File("sounds/test.wav".to_string()))
+            .unwrap()
+            .count();
+        system.layers[0].queue.push_back(PlaySoundCommand {
+            repeat: Repeat::Forever,
+            event_sender: event_sender.into(),
+            ..PlaySoundCommand::mock("sounds/test.wav", SoundPriority::Default, None)
+        });
+        system.update_playback();
+        assert!(matches!(events.try_recv(), Ok(PlaybackEvent::Started)));
+
+        // sound keeps playing until it is cancelled
+        while rendered.len() < len * 3 {
+            system.update_playback();
+        }
+        assert!(events.try_recv().is_err());
+        command_sender
+            .send(SoundCommand::Cancel(PlaybackId(0)))
+            .unwrap();
+        let _ = system.process_incoming_commands();
+        assert!(system.layers[0].current_sound.is_none());
+        assert!(matches!(events.try_recv(), Ok(PlaybackEvent::Cancelled)));
     }
 
     fn mock_system() -> (OrbSoundSystem, Sender<SoundCommand>) {
+        let (system, tx, _) = mock_rendering_system();
+        (system, tx)
+    }
+
+    /// Creates system rendering its output into memory.
+    fn mock_rendering_system() -> (OrbSoundSystem, Sender<SoundCommand>, RenderBuffer) {
+        mock_layered_system(&[DEFAULT_LAYER], Fades::default())
+    }
+
+    /// Creates system with given layers rendering its output into memory.
+    fn mock_layered_system(
+        names: &[&str],
+        fades: Fades,
+    ) -> (OrbSoundSystem, Sender<SoundCommand>, RenderBuffer) {
         let (tx, rx) = mpsc::channel::<SoundCommand>();
+        let (sink, sink_output) = Sink::new_idle();
+        let (mut output, rendered) = RenderOutput::memory(2, 44100);
+        output.start(sink_output).unwrap();
+        let fader = Arc::new(Fader::new(1.0));
+        let (layers, layer_outputs): (Vec<_>, Vec<_>) = names
+            .iter()
+            .map(|name| Layer::new((*name).into(), fades, None, fader.clone()))
+            .unzip();
+        let mixer = Mixer::new(2, 44100, layer_outputs);
+        let mixer_controls = mixer.controls();
+        sink.append(mixer);
         let system = OrbSoundSystem {
             command_receiver: rx,
-            queue: VecDeque::new(),
-            sink: Sink::new_idle().0,
-            current_sound: None,
-            _output_stream: OutputStream::try_default().unwrap().0
+            layers,
+            ducking: Vec::new(),
+            mixer: mixer_controls,
+            limiter: None,
+            fades,
+            volume: Volume::new(VolumeLimits::default()),
+            fader,
+            pause_at: None,
+            state_file: None,
+            saved_state: SavedState::default(),
+            save_at: None,
+            shutdown_requesters: Vec::new(),
+            sink,
+            preemption: PreemptionPolicy::default(),
+            events: EventBus::default(),
+            output: Box::new(output),
+            null_output: false,
+            clock: Arc::new(SystemClock),
         };
-        (system, tx)
+        (system, tx, rendered)
     }
 }
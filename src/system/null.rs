@@ -0,0 +1,97 @@
+//! Output backend discarding samples at real time pace, for machines without sound card.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rodio::Source;
+
+use crate::system::output::{OutputBackend, SinkOutput};
+use crate::OrbSoundSystemError;
+
+// How often discarding thread wakes up to consume samples
+const DISCARD_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Output backend which consumes samples at real time pace and throws them away. Sounds take as
+/// long to play as on sound device, so scheduling, deadlines and notifications behave the same way.
+#[derive(Debug, Default)]
+pub struct NullOutput {
+    /// Tells discarding thread to stop
+    stopped: Arc<AtomicBool>,
+}
+
+impl NullOutput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl OutputBackend for NullOutput {
+    fn start(&mut self, mut output: SinkOutput) -> Result<(), OrbSoundSystemError> {
+        let stopped = self.stopped.clone();
+        thread::spawn(move || {
+            let started = Instant::now();
+            // Playback time of discarded samples, in seconds
+            let mut discarded = 0.0;
+            while !stopped.load(Ordering::Relaxed) {
+                let elapsed = started.elapsed().as_secs_f64();
+                while discarded < elapsed {
+                    if output.next().is_none() {
+                        return;
+                    }
+                    let samples_per_sec = output.sample_rate() as f64 * output.channels() as f64;
+                    discarded += 1.0 / samples_per_sec.max(1.0);
+                }
+                thread::sleep(DISCARD_INTERVAL);
+            }
+        });
+        Ok(())
+    }
+}
+
+impl Drop for NullOutput {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use rodio::buffer::SamplesBuffer;
+    use rodio::Sink;
+
+    use crate::system::null::NullOutput;
+    use crate::system::output::OutputBackend;
+    use crate::OrbSoundSystem;
+
+    #[test]
+    fn real_time_pace() {
+        let (sink, sink_output) = Sink::new_idle();
+        let mut output = NullOutput::new();
+        output.start(sink_output).unwrap();
+
+        let started = Instant::now();
+        // 50ms of stereo 1kHz sound
+        sink.append(SamplesBuffer::new(2, 1000, vec![0i16; 100]));
+        while !sink.empty() {
+            assert!(started.elapsed() < Duration::from_secs(1));
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(started.elapsed() >= Duration::from_millis(45));
+    }
+
+    #[test]
+    fn fallback() {
+        let result = OrbSoundSystem::builder()
+            .device("missing")
+            .require_device()
+            .run();
+        assert!(result.is_err());
+        let mut handle = OrbSoundSystem::builder().device("missing").run().unwrap();
+        assert!(handle.status().unwrap().null_output);
+        handle.shutdown().unwrap();
+    }
+}
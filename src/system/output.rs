@@ -0,0 +1,51 @@
+use std::time::Instant;
+
+use rodio::queue::SourcesQueueOutput;
+
+use crate::handle::DeviceEvent;
+use crate::OrbSoundSystemError;
+
+/// Samples played by the sink, to be consumed by an [`OutputBackend`].
+pub type SinkOutput = SourcesQueueOutput<f32>;
+
+/// Destination of samples played by the system. Sound device is used by default, other backend
+/// can be set with [`OrbSoundSystemBuilder::output()`].
+///
+/// [`OrbSoundSystemBuilder::output()`]: crate::system::OrbSoundSystemBuilder::output
+pub trait OutputBackend {
+    /// Start consuming samples of the sink. Called once on event loop thread during system
+    /// initialization.
+    fn start(&mut self, output: SinkOutput) -> Result<(), OrbSoundSystemError>;
+
+    /// Returns true if backend consumes samples on its own at real time pace, like sound device
+    /// does. Otherwise event loop calls [`OutputBackend::render()`] as fast as it can while there
+    /// is something to play, which makes output independent of timing.
+    fn is_realtime(&self) -> bool {
+        true
+    }
+
+    /// Consume next portion of samples. Called by event loop between refills of ring buffer, only
+    /// for backends which are not real time.
+    fn render(&mut self) {}
+
+    /// Check state of the backend, e.g. whether sound device still works. Called on every event
+    /// loop iteration. Returns event to be reported to subscribers, if any.
+    fn check(&mut self, _now: Instant) -> Option<DeviceEvent> {
+        None
+    }
+
+    /// Returns the moment [`OutputBackend::check()`] has to be called even if nothing else wakes
+    /// event loop up.
+    fn next_check(&self) -> Option<Instant> {
+        None
+    }
+
+    /// Complete the output, e.g. write file trailer. Called once on event loop thread when the
+    /// system shuts down, before [`OrbSoundSystemHandle::shutdown()`] returns. Returns event to be
+    /// reported to subscribers, if any.
+    ///
+    /// [`OrbSoundSystemHandle::shutdown()`]: crate::OrbSoundSystemHandle::shutdown
+    fn finish(&mut self) -> Option<DeviceEvent> {
+        None
+    }
+}
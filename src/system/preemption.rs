@@ -0,0 +1,43 @@
+use std::time::Duration;
+
+/// Defines what happens to currently playing sound when [`SoundPriority::Urgent`] sound is
+/// requested while a sound of lower priority is playing.
+///
+/// Sounds put back to the queue lose their play deadline, they have already started and are played
+/// once urgent sounds are over however long that takes.
+///
+/// [`SoundPriority::Urgent`]: crate::handle::SoundPriority::Urgent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PreemptionPolicy {
+    /// How currently playing sound is interrupted
+    pub mode: PreemptionMode,
+    /// What happens to interrupted sound
+    pub preempted: PreemptedSound,
+}
+
+/// How currently playing sound is interrupted by urgent one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PreemptionMode {
+    /// Urgent sound waits for currently playing sound to finish
+    #[default]
+    Never,
+    /// Currently playing sound is stopped immediately
+    Interrupt,
+    /// Currently playing sound fades out during given duration, then urgent sound starts
+    FadeOut(Duration),
+}
+
+/// What happens to a sound interrupted by urgent one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PreemptedSound {
+    /// Sound is cancelled
+    #[default]
+    Drop,
+    /// Sound is put back to the queue and played from the beginning later. It has already started,
+    /// so its play deadline no longer applies
+    Restart,
+    /// Sound is put back to the queue and continues later from the sample heard when it was
+    /// interrupted, or when it started fading out. It has already started, so its play deadline no
+    /// longer applies
+    Resume,
+}
@@ -0,0 +1,318 @@
+//! Output backend rendering samples into WAV file or memory instead of playing them.
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use rodio::buffer::SamplesBuffer;
+use rodio::cpal::Sample;
+use rodio::source::UniformSourceIterator;
+use rodio::Source;
+
+use crate::handle::DeviceEvent;
+use crate::system::output::{OutputBackend, SinkOutput};
+use crate::OrbSoundSystemError;
+
+// Size of WAV header written before samples
+const WAV_HEADER_LEN: u32 = 44;
+
+/// Output backend which renders samples into WAV file or memory. Event loop drives it as fast as
+/// it can while there is something to play, so sounds are rendered faster than real time.
+/// Nothing is rendered while playback is paused.
+///
+/// Rendered output is not fully determined by issued commands: playback is paused once the pause
+/// fade runs out and play deadlines expire by the [`Clock`] of the system, while samples are
+/// rendered at whatever pace the event loop manages. Use [`ManualClock`] and zero pause fade of
+/// [`OrbSoundSystemBuilder::fades()`] to get the same output on every run.
+///
+/// [`Clock`]: crate::clock::Clock
+/// [`OrbSoundSystemBuilder::fades()`]: crate::system::OrbSoundSystemBuilder::fades
+/// [`ManualClock`]: crate::clock::ManualClock
+pub struct RenderOutput {
+    channels: u16,
+    sample_rate: u32,
+    target: RenderTarget,
+    /// Set once output is started
+    source: Option<SinkOutput>,
+    /// Sample pulled from the source which did not fit previous run, along with its format
+    pending: Option<(f32, u16, u32)>,
+    /// Error which occurred while writing samples, reported on next check
+    error: Option<String>,
+}
+
+enum RenderTarget {
+    Wav(WavWriter),
+    Memory(RenderBuffer),
+}
+
+impl RenderOutput {
+    /// Create backend rendering 16 bit samples with given format into WAV file at `path`. File is
+    /// completed when the system shuts down.
+    pub fn wav_file(
+        path: impl AsRef<Path>,
+        channels: u16,
+        sample_rate: u32,
+    ) -> Result<Self, OrbSoundSystemError> {
+        let path = path.as_ref();
+        let writer = WavWriter::create(path, channels, sample_rate)
+            .map_err(|e| OrbSoundSystemError::OutputErr(format!("{}: {}", path.display(), e)))?;
+        Ok(Self::new(channels, sample_rate, RenderTarget::Wav(writer)))
+    }
+
+    /// Create backend rendering samples with given format into memory. Rendered samples can be
+    /// retrieved from returned [`RenderBuffer`].
+    pub fn memory(channels: u16, sample_rate: u32) -> (Self, RenderBuffer) {
+        let buffer = RenderBuffer::default();
+        let output = Self::new(channels, sample_rate, RenderTarget::Memory(buffer.clone()));
+        (output, buffer)
+    }
+
+    fn new(channels: u16, sample_rate: u32, target: RenderTarget) -> Self {
+        Self {
+            channels,
+            sample_rate,
+            target,
+            source: None,
+            pending: None,
+            error: None,
+        }
+    }
+}
+
+impl RenderOutput {
+    /// Pull up to 10ms of samples sharing the same format from the sink. Format of the sink output
+    /// is checked after every sample, because the sink reports format of a new sound only once
+    /// its first sample is pulled.
+    fn next_run(&mut self) -> Option<(Vec<f32>, u16, u32)> {
+        let source = self.source.as_mut()?;
+        let (first, channels, sample_rate) = match self.pending.take() {
+            Some(pending) => pending,
+            None => {
+                let sample = source.next()?;
+                (sample, source.channels(), source.sample_rate())
+            }
+        };
+        let len = (sample_rate / 100) as usize * channels as usize;
+        let mut samples = Vec::with_capacity(len);
+        samples.push(first);
+        while samples.len() < len {
+            let sample = match source.next() {
+                Some(sample) => sample,
+                None => break,
+            };
+            if (source.channels(), source.sample_rate()) != (channels, sample_rate) {
+                self.pending = Some((sample, source.channels(), source.sample_rate()));
+                break;
+            }
+            samples.push(sample);
+        }
+        Some((samples, channels, sample_rate))
+    }
+}
+
+impl OutputBackend for RenderOutput {
+    fn start(&mut self, output: SinkOutput) -> Result<(), OrbSoundSystemError> {
+        self.source = Some(output);
+        Ok(())
+    }
+
+    fn is_realtime(&self) -> bool {
+        false
+    }
+
+    /// Renders about 10ms of samples.
+    fn render(&mut self) {
+        let (samples, channels, sample_rate) = match self.next_run() {
+            Some(run) => run,
+            None => return,
+        };
+        let samples = if (channels, sample_rate) == (self.channels, self.sample_rate) {
+            samples
+        } else {
+            let run = SamplesBuffer::new(channels, sample_rate, samples);
+            UniformSourceIterator::new(run, self.channels, self.sample_rate).collect()
+        };
+        let chunk = samples.into_iter().map(|sample| sample.to_i16());
+        match &mut self.target {
+            RenderTarget::Wav(writer) => {
+                if let Err(e) = writer.write(chunk) {
+                    self.error.get_or_insert(e.to_string());
+                }
+            }
+            RenderTarget::Memory(buffer) => buffer.samples.lock().unwrap().extend(chunk),
+        }
+    }
+
+    fn check(&mut self, _now: Instant) -> Option<DeviceEvent> {
+        self.error.take().map(DeviceEvent::WriteFailed)
+    }
+
+    /// Updates header of WAV file, reports error of writing samples if it has not been reported
+    /// yet.
+    fn finish(&mut self) -> Option<DeviceEvent> {
+        if let RenderTarget::Wav(writer) = &mut self.target {
+            if let Err(e) = writer.finish() {
+                self.error.get_or_insert(e.to_string());
+            }
+        }
+        self.error.take().map(DeviceEvent::WriteFailed)
+    }
+}
+
+/// Samples rendered by [`RenderOutput`] into memory. Can be cheaply cloned, all clones share the
+/// same samples.
+#[derive(Debug, Clone, Default)]
+pub struct RenderBuffer {
+    samples: Arc<Mutex<Vec<i16>>>,
+}
+
+impl RenderBuffer {
+    /// Returns copy of samples rendered so far, channels are interleaved.
+    pub fn samples(&self) -> Vec<i16> {
+        self.samples.lock().unwrap().clone()
+    }
+
+    /// Returns number of samples rendered so far.
+    pub fn len(&self) -> usize {
+        self.samples.lock().unwrap().len()
+    }
+
+    /// Returns true if nothing has been rendered yet.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Writes 16 bit PCM WAV file. Sizes in the header are updated when the writer is finished, or
+/// dropped without being finished.
+struct WavWriter {
+    file: BufWriter<File>,
+    data_len: u32,
+    finished: bool,
+}
+
+impl WavWriter {
+    fn create(path: &Path, channels: u16, sample_rate: u32) -> std::io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let block_align = channels * 2;
+        file.write_all(b"RIFF")?;
+        file.write_all(&(WAV_HEADER_LEN - 8).to_le_bytes())?;
+        file.write_all(b"WAVEfmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        // PCM format
+        file.write_all(&1u16.to_le_bytes())?;
+        file.write_all(&channels.to_le_bytes())?;
+        file.write_all(&sample_rate.to_le_bytes())?;
+        file.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        file.write_all(&block_align.to_le_bytes())?;
+        file.write_all(&16u16.to_le_bytes())?;
+        file.write_all(b"data")?;
+        file.write_all(&0u32.to_le_bytes())?;
+        Ok(Self {
+            file,
+            data_len: 0,
+            finished: false,
+        })
+    }
+
+    fn write(&mut self, samples: impl Iterator<Item = i16>) -> std::io::Result<()> {
+        for sample in samples {
+            self.file.write_all(&sample.to_le_bytes())?;
+            self.data_len += 2;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.finished = true;
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file
+            .write_all(&(WAV_HEADER_LEN - 8 + self.data_len).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(WAV_HEADER_LEN as u64 - 4))?;
+        self.file.write_all(&self.data_len.to_le_bytes())?;
+        self.file.flush()
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        if !self.finished {
+            // Nobody to report the error to
+            let _ = self.finish();
+        }
+    }
+}
+
+#[cfg(test)]
+// Some of the tests decode WAV files
+#[cfg_attr(not(feature = "wav"), allow(unused_imports, dead_code))]
+mod test {
+    use rodio::Sink;
+
+    use crate::handle::{SoundPriority, SoundSource};
+    use crate::system::output::OutputBackend;
+    use crate::system::render::RenderOutput;
+    use crate::system::sound;
+    use crate::OrbSoundSystem;
+
+    #[test]
+    fn render_to_memory() {
+        let (sink, sink_output) = Sink::new_idle();
+        let (mut output, buffer) = RenderOutput::memory(2, 1000);
+        output.render();
+        assert!(buffer.is_empty());
+
+        output.start(sink_output).unwrap();
+        sink.append(rodio::buffer::SamplesBuffer::new(2, 1000, vec![0.5f32; 30]));
+        output.render();
+        // 10ms of stereo 1kHz sound
+        assert_eq!(buffer.samples(), vec![16383i16; 20]);
+        // rest of the sound, sink output is kept alive with silence afterwards
+        output.render();
+        assert_eq!(buffer.samples(), vec![16383i16; 30]);
+        output.render();
+        assert_eq!(buffer.len(), 30 + 20);
+        assert!(buffer.samples()[30..].iter().all(|&s| s == 0));
+    }
+
+    #[test]
+    #[cfg(feature = "wav")]
+    fn render_to_wav_file() {
+        let path = std::env::temp_dir().join("orb_sound_render_test.wav");
+        let (sink, sink_output) = Sink::new_idle();
+        let mut output = RenderOutput::wav_file(&path, 2, 1000).unwrap();
+        output.start(sink_output).unwrap();
+        sink.append(rodio::buffer::SamplesBuffer::new(
+            2,
+            1000,
+            vec![-0.5f32; 20],
+        ));
+        output.render();
+        assert_eq!(output.finish(), None);
+
+        let reader = sound::open(&SoundSource::File(path.to_str().unwrap().to_string())).unwrap();
+        assert_eq!((reader.channels(), reader.sample_rate()), (2, 1000));
+        assert_eq!(reader.collect::<Vec<_>>(), vec![-16384i16; 20]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "wav")]
+    fn complete_on_shutdown() {
+        let path = std::env::temp_dir().join("orb_sound_render_shutdown_test.wav");
+        let output = RenderOutput::wav_file(&path, 2, 44100).unwrap();
+        let mut handle = OrbSoundSystem::builder().output(output).run().unwrap();
+        handle
+            .play_and_wait("sounds/test.wav", SoundPriority::High, None, None)
+            .unwrap();
+        handle.shutdown().unwrap();
+
+        // file is complete as soon as shutdown returns
+        let source = |path: &str| SoundSource::File(path.to_string());
+        let expected = sound::open(&source("sounds/test.wav")).unwrap().count();
+        let rendered = sound::open(&source(path.to_str().unwrap())).unwrap();
+        assert!(rendered.count() >= expected);
+        std::fs::remove_file(path).unwrap();
+    }
+}
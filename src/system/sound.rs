@@ -1,20 +1,39 @@
+use std::collections::VecDeque;
 use std::fs::File;
-use std::io::BufReader;
-use std::time::Duration;
+use std::io::{BufReader, Cursor};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use rodio::{Decoder, Sample, Sink, Source};
+use rodio::cpal::Sample as _;
+use rodio::source::Empty;
+use rodio::{Sample, Sink, Source};
 use rtrb::{Consumer, Producer, RingBuffer};
 
+use crate::handle::{Repeat, SoundSource};
+use crate::system::fade::{Fader, Gain};
+use crate::system::format;
 use crate::OrbSoundSystemError;
 
 // Buffer that may contain up to 50ms of wav data with 44100 sample rate
 const BUFFER_CAPACITY: usize = 44100 / 20 * 2;
 
+// Repeated sounds up to 1M samples long, which is about 12s of stereo sound with 44100 sample
+// rate, are decoded once and then repeated from memory
+const REPEAT_CACHE_CAPACITY: usize = 1 << 20;
+
+/// Decoded samples of a sound source. Decoder type depends on the format and kind of the source,
+/// which are known only at runtime, so it is boxed.
+pub(crate) type SoundReader = Box<dyn Source<Item = i16> + Send>;
+
 /// Type representing sound currently being played. Backed by ring buffer and consists of two parts:
 ///
 /// - A consumer part represented by [`SoundConsumer`] which is used to read sound samples.
 /// - A producer part represented by [`SoundProducer`] which is used to write sound samples.
-pub(crate) type Sound = SoundProducer<Decoder<BufReader<File>>>;
+///
+/// [`SoundProducer`] is generic over its reader, sounds of the system read any [`SoundSource`]
+/// through [`Playback`].
+pub(crate) type Sound = SoundProducer<Playback>;
 
 /// Producer part of a ring buffer. User of the type is responsible for keeping ring buffer full
 /// using [`SoundProducer::fill_buffer()`] associated function.
@@ -23,38 +42,353 @@ pub(crate) struct SoundProducer<I> {
     reader: I,
     /// Ring buffer producer
     buffer: Producer<i16>,
+    /// Number of channels of the source
+    channels: u16,
+    /// Sample rate of the source
+    sample_rate: u32,
+    /// Set by consumer part once all samples are played
+    played: Arc<AtomicBool>,
+    /// Fade of the sound applied by consumer part
+    fade: Arc<Fader>,
+    /// Fade out in progress, if any
+    fade_out: Option<FadeOut>,
+    /// Number of samples written to ring buffer
+    written: u64,
+    /// Samples written to ring buffer as read from the reader, covering all samples which may not
+    /// have been played yet. Kept to resume the sound from the sample heard when it was suspended
+    history: VecDeque<i16>,
+}
+
+/// Progress of fading out, measured in samples.
+struct FadeOut {
+    /// Position of the sample being played when fade out started
+    position: u64,
+    remaining: u64,
+    total: u64,
 }
 
-impl SoundProducer<Decoder<BufReader<File>>> {
-    /// Start playing a file located by `path`. Creates producer and consumer parts of ring buffer
+impl SoundProducer<Playback> {
+    /// Start playing sound from given `source`. Creates producer and consumer parts of ring buffer
     /// and fills it with data. Consumer pushed to the output stream and producer returned to the
     /// caller which is responsible for keeping ring buffer full.
+    ///
+    /// Sound is played as many times as `repeat` says with `gap` of silence between repeats.
+    /// Consumer part scales samples by linear `gain`. Sound fades in during `fade_in` and follows
+    /// volume and pause state of `output`.
     pub fn play(
-        path: &str,
+        source: &SoundSource,
         sink: &Sink,
+        repeat: Repeat,
+        gap: Duration,
+        gain: f32,
+        fade_in: Duration,
+        output: &Arc<Fader>,
     ) -> Result<Sound, OrbSoundSystemError> {
-        let (producer, consumer) = RingBuffer::new(BUFFER_CAPACITY);
+        let reader = open(source)?;
+        let reader = match repeat {
+            // sound is played at least once
+            Repeat::Once | Repeat::Times(0 | 1) => Playback::Once(reader),
+            Repeat::Times(times) => Playback::Repeated(RepeatedReader::new(
+                source.clone(),
+                reader,
+                Some(times - 1),
+                gap,
+            )),
+            Repeat::Forever => {
+                Playback::Repeated(RepeatedReader::new(source.clone(), reader, None, gap))
+            }
+        };
+        Ok(Self::resume(reader, sink, gain, fade_in, output))
+    }
+
+    /// Returns the error which ended the sound early, if any. A repeated sound fails if its source
+    /// can not be opened again for the next repeat.
+    pub fn take_error(&mut self) -> Option<OrbSoundSystemError> {
+        match &mut self.reader {
+            Playback::Once(_) => None,
+            Playback::Repeated(reader) => reader.error.take(),
+            Playback::Resumed(_, reader) => reader.take_error(),
+        }
+    }
+
+    /// Stop producing samples the same way as [`SoundProducer::into_tail()`] does, but keep the
+    /// reader so the sound can be resumed later. Resumed sound continues from the sample being
+    /// played when the sound was suspended or, if it was faded out, when fade out started.
+    pub fn into_reader(mut self) -> Playback {
+        let position = match &self.fade_out {
+            Some(fade_out) => fade_out.position,
+            None => self.written - self.buffered_samples(),
+        };
+        // consumer may be in the middle of a frame, resume from its first sample
+        let position = position - position % self.channels.max(1) as u64;
+        let unplayed = (self.written - position) as usize;
+        let played = self.history.len().saturating_sub(unplayed);
+        self.history.drain(..played);
+        Playback::Resumed(self.history, Box::new(self.reader))
+    }
+}
+
+impl Playback {
+    fn take_error(&mut self) -> Option<OrbSoundSystemError> {
+        match self {
+            Playback::Once(_) => None,
+            Playback::Repeated(reader) => reader.error.take(),
+            Playback::Resumed(_, reader) => reader.take_error(),
+        }
+    }
+}
+
+/// Samples of a sound being played, either once or repeatedly.
+pub(crate) enum Playback {
+    Once(SoundReader),
+    Repeated(RepeatedReader),
+    /// Suspended sound: samples written but not played before it was suspended, followed by the
+    /// rest of the sound
+    Resumed(VecDeque<i16>, Box<Playback>),
+}
+
+impl Iterator for Playback {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Playback::Once(reader) => reader.next(),
+            Playback::Repeated(reader) => reader.next(),
+            Playback::Resumed(unplayed, reader) => unplayed.pop_front().or_else(|| reader.next()),
+        }
+    }
+}
+
+impl Source for Playback {
+    fn current_frame_len(&self) -> Option<usize> {
+        match self {
+            Playback::Once(reader) => reader.current_frame_len(),
+            Playback::Repeated(reader) => reader.current_frame_len(),
+            Playback::Resumed(..) => None,
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        match self {
+            Playback::Once(reader) => reader.channels(),
+            Playback::Repeated(reader) => reader.channels(),
+            Playback::Resumed(_, reader) => reader.channels(),
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        match self {
+            Playback::Once(reader) => reader.sample_rate(),
+            Playback::Repeated(reader) => reader.sample_rate(),
+            Playback::Resumed(_, reader) => reader.sample_rate(),
+        }
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        match self {
+            Playback::Once(reader) => reader.total_duration(),
+            Playback::Repeated(reader) => reader.total_duration(),
+            Playback::Resumed(..) => None,
+        }
+    }
+}
+
+/// Reader playing a sound several times with silence between repeats, so repeats are written to
+/// the same ring buffer one after another. Samples of a short sound are kept in memory while it is
+/// decoded the first time, longer sounds are opened again once they end.
+pub(crate) struct RepeatedReader {
+    source: SoundSource,
+    /// Decoder of the current repeat, empty once the sound is repeated from cache
+    reader: SoundReader,
+    channels: u16,
+    sample_rate: u32,
+    /// Samples decoded so far, dropped once the sound turns out to be too long or if the source is
+    /// already in memory
+    cache: Option<Vec<i16>>,
+    /// Position of the next sample within cache, set once the whole sound is cached
+    cached: Option<usize>,
+    /// Error opening the source again, which ended the sound
+    error: Option<OrbSoundSystemError>,
+    /// Repeats left after the current one, `None` if the sound repeats until it is cancelled
+    remaining: Option<u32>,
+    /// Number of silent samples between repeats
+    gap: u64,
+    /// Silent samples left before the current repeat starts
+    gap_left: u64,
+    /// Set until the current repeat returns its first sample, so that a sound without samples
+    /// does not repeat forever
+    empty: bool,
+}
 
-        let file = File::open(path).map_err(|e| {
-            OrbSoundSystemError::SoundFileErr(format!("{}: {}", path, e.to_string()))
-        })?;
-        let decoder = Decoder::new_wav(BufReader::new(file)).map_err(|e| {
-            OrbSoundSystemError::SoundFileErr(format!("{}: {}", path, e.to_string()))
-        })?;
-        let source = SoundConsumer {
-            buffer: consumer,
-            channels: decoder.channels(),
-            sample_rate: decoder.sample_rate(),
+impl RepeatedReader {
+    fn new(
+        source: SoundSource,
+        reader: SoundReader,
+        remaining: Option<u32>,
+        gap: Duration,
+    ) -> Self {
+        let frames = gap.as_micros() as u64 * reader.sample_rate() as u64 / 1_000_000;
+        let cache = match source {
+            SoundSource::Preloaded(_) => None,
+            _ => Some(Vec::new()),
         };
+        Self {
+            gap: frames * reader.channels() as u64,
+            channels: reader.channels(),
+            sample_rate: reader.sample_rate(),
+            source,
+            reader,
+            cache,
+            cached: None,
+            error: None,
+            remaining,
+            gap_left: 0,
+            empty: true,
+        }
+    }
+}
+
+impl Iterator for RepeatedReader {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.gap_left > 0 {
+                self.gap_left -= 1;
+                return Some(<i16 as Sample>::zero_value());
+            }
+            let sample = match (self.cached.as_mut(), self.cache.as_mut()) {
+                (Some(position), Some(cache)) => {
+                    let sample = cache.get(*position).copied();
+                    *position += 1;
+                    sample
+                }
+                (_, cache) => {
+                    let sample = self.reader.next();
+                    match (sample, cache) {
+                        (Some(sample), Some(cache)) if cache.len() < REPEAT_CACHE_CAPACITY => {
+                            cache.push(sample)
+                        }
+                        (Some(_), Some(_)) => self.cache = None,
+                        _ => {}
+                    }
+                    sample
+                }
+            };
+            if let Some(sample) = sample {
+                self.empty = false;
+                return Some(sample);
+            }
+            if self.empty {
+                return None;
+            }
+            match self.remaining.as_mut() {
+                Some(0) => return None,
+                Some(remaining) => *remaining -= 1,
+                None => {}
+            }
+            if self.cache.is_some() {
+                // whole sound is in memory, decoder is not needed anymore
+                self.reader = Box::new(Empty::new());
+                self.cached = Some(0);
+            } else {
+                // Source was opened before, so it fails only if the file is gone since then
+                match open(&self.source) {
+                    Ok(reader) => self.reader = reader,
+                    Err(err) => {
+                        self.error = Some(err);
+                        return None;
+                    }
+                }
+            }
+            self.gap_left = self.gap;
+            self.empty = true;
+        }
+    }
+}
+
+impl Source for RepeatedReader {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Open sound `source` and prepare it for decoding. Fails if the file does not exist or its format
+/// is not supported.
+pub(crate) fn open(source: &SoundSource) -> Result<SoundReader, OrbSoundSystemError> {
+    match source {
+        SoundSource::File(path) => {
+            let file = File::open(path)
+                .map_err(|e| OrbSoundSystemError::SoundFileErr(format!("{}: {}", path, e)))?;
+            Ok(Box::new(format::decode(BufReader::new(file), path)?))
+        }
+        SoundSource::Bytes(data) => {
+            let name = source.to_string();
+            Ok(Box::new(format::decode(Cursor::new(data.clone()), &name)?))
+        }
+        SoundSource::StaticBytes(data) => {
+            let name = source.to_string();
+            Ok(Box::new(format::decode(Cursor::new(*data), &name)?))
+        }
+        SoundSource::Preloaded(sound) => Ok(Box::new(sound.reader())),
+    }
+}
+
+impl<I> SoundProducer<I>
+where
+    I: Source<Item = i16> + Send + 'static,
+{
+    /// Continue playing samples of `reader`, which is either a fresh source or one returned by
+    /// [`SoundProducer::into_reader()`]. Works the same way as [`SoundProducer::play()`].
+    pub fn resume(
+        reader: I,
+        sink: &Sink,
+        gain: f32,
+        fade_in: Duration,
+        output: &Arc<Fader>,
+    ) -> Self {
+        let (producer, consumer) = RingBuffer::new(BUFFER_CAPACITY);
+        let played = Arc::new(AtomicBool::new(false));
+        // sound starts silent and ramps up to full volume
+        let fade = Arc::new(Fader::new(0.0));
+        let source = SoundConsumer::new(
+            consumer,
+            reader.channels(),
+            reader.sample_rate(),
+            played.clone(),
+            gain,
+            fade.clone(),
+            output.clone(),
+        );
+        fade.set_volume(1.0, fade_in);
 
         let mut sound = SoundProducer {
-            reader: decoder,
             buffer: producer,
+            channels: reader.channels(),
+            sample_rate: reader.sample_rate(),
+            played,
+            fade,
+            fade_out: None,
+            written: 0,
+            history: VecDeque::new(),
+            reader,
         };
         sound.fill_buffer();
         sink.append(source);
 
-        Ok(sound)
+        sound
     }
 }
 
@@ -63,41 +397,195 @@ where
     I: Iterator<Item = i16>,
 {
     /// Fill available slots of ring buffer with sound samples from underlying reader. Returns true
-    /// if underlying reader is out of data.
+    /// if underlying reader is out of data, fade out has completed or consumer has ended after
+    /// [`SoundProducer::stop()`].
     pub fn fill_buffer(&mut self) -> bool {
+        if self.played.load(Ordering::Acquire) {
+            return true;
+        }
         let slots_available = self.buffer.slots();
+        // while fading out history keeps every sample since fade out started
+        let history_capacity = self.buffer.buffer().capacity() + self.channels as usize;
         for _ in 0..slots_available {
+            let gain = match self.fade_out.as_mut() {
+                Some(fade_out) if fade_out.remaining == 0 => return true,
+                Some(fade_out) => {
+                    fade_out.remaining -= 1;
+                    Some(fade_out.remaining as f32 / fade_out.total as f32)
+                }
+                None => None,
+            };
             if let Some(sample) = self.reader.next() {
+                self.history.push_back(sample);
+                if gain.is_none() && self.history.len() > history_capacity {
+                    self.history.pop_front();
+                }
+                let sample = gain.map_or(sample, |gain| sample.amplify(gain));
                 // Unwrap is safe here because we checked slots availability
                 self.buffer.push(sample).unwrap();
+                self.written += 1;
             } else {
                 return true;
             }
         }
         false
     }
+
+    /// Start fading out. Sound ends after given `duration`, which is counted from samples not yet
+    /// written to ring buffer.
+    pub fn fade_out(&mut self, duration: Duration) {
+        let samples_per_sec = self.sample_rate as u64 * self.channels as u64;
+        let total = (duration.as_micros() as u64 * samples_per_sec / 1_000_000).max(1);
+        self.fade_out = Some(FadeOut {
+            position: self.written - self.buffered_samples(),
+            remaining: total,
+            total,
+        });
+    }
+
+    /// Stop the sound by fading it out during `duration` starting from the sample being played
+    /// right now. Ring buffer has to be kept full until consumer ends, which is reported by
+    /// [`SoundProducer::fill_buffer()`].
+    pub fn stop(&mut self, duration: Duration) {
+        self.fade.pause(duration);
+    }
+
+    /// Time left until half of the ring buffer is consumed. This is the moment the buffer should
+    /// be refilled to stay away from under-run.
+    pub fn refill_in(&self) -> Duration {
+        self.buffered() / 2
+    }
+
+    /// Playback time of samples consumed from ring buffer so far.
+    pub fn elapsed(&self) -> Duration {
+        self.duration_of(self.written - self.buffered_samples())
+    }
+
+    /// Duration of sound samples waiting in ring buffer.
+    fn buffered(&self) -> Duration {
+        self.duration_of(self.buffered_samples())
+    }
+
+    fn buffered_samples(&self) -> u64 {
+        (self.buffer.buffer().capacity() - self.buffer.slots()) as u64
+    }
+
+    fn duration_of(&self, samples: u64) -> Duration {
+        let samples_per_sec = (self.sample_rate as u64 * self.channels as u64).max(1);
+        Duration::from_micros(samples * 1_000_000 / samples_per_sec)
+    }
+
+    /// Stop producing samples. Abandons ring buffer, so consumer plays what is left in the buffer
+    /// and then ends. Returns [`SoundTail`] to track when that happens, estimated from `now`.
+    pub fn into_tail(self, now: Instant) -> SoundTail {
+        SoundTail {
+            ends_at: now + self.buffered(),
+            played: self.played,
+        }
+    }
+}
+
+/// Samples of a sound left in abandoned ring buffer. See [`SoundProducer::into_tail()`].
+pub(crate) struct SoundTail {
+    /// Estimated moment consumer plays the last sample
+    ends_at: Instant,
+    played: Arc<AtomicBool>,
+}
+
+impl SoundTail {
+    /// Estimated moment the sound ends. Actual end may be later if playback is paused or output
+    /// device lags behind.
+    pub fn ends_at(&self) -> Instant {
+        self.ends_at
+    }
+
+    /// Returns true once consumer has played all samples.
+    pub fn is_played(&self) -> bool {
+        self.played.load(Ordering::Acquire)
+    }
 }
 
-/// Consumer part of ring buffer.
+/// Consumer part of ring buffer. Applies gain and fades of the sound and of the whole output to
+/// every frame. Samples are converted to `f32`, so gain and volume above 1.0 do not clip them
+/// before the output limiter.
 struct SoundConsumer {
     buffer: Consumer<i16>,
     channels: u16,
     sample_rate: u32,
+    /// Shared with producer part, set once the last sample is played
+    played: Arc<AtomicBool>,
+    /// Linear gain of the sound requested by its player
+    sound_gain: f32,
+    /// Fade of the sound, shared with producer part. Consumer ends once the sound is stopped and
+    /// faded out
+    fade: Arc<Fader>,
+    fade_gain: Gain,
+    /// Fade of the whole output. Consumer holds its samples while the output is paused and faded
+    /// out
+    output: Arc<Fader>,
+    output_gain: Gain,
+    /// Gain applied to samples of current frame
+    gain: f32,
+    /// Position of the next sample within current frame
+    position: u16,
+}
+
+impl SoundConsumer {
+    fn new(
+        buffer: Consumer<i16>,
+        channels: u16,
+        sample_rate: u32,
+        played: Arc<AtomicBool>,
+        sound_gain: f32,
+        fade: Arc<Fader>,
+        output: Arc<Fader>,
+    ) -> Self {
+        Self {
+            buffer,
+            channels,
+            sample_rate,
+            played,
+            sound_gain,
+            fade_gain: Gain::new(&fade),
+            fade,
+            output_gain: Gain::new(&output),
+            output,
+            gain: 1.0,
+            position: 0,
+        }
+    }
 }
 
 impl Iterator for SoundConsumer {
-    type Item = i16;
+    type Item = f32;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if self.position == 0 {
+            let fade = self.fade_gain.next(&self.fade, self.sample_rate);
+            let output = self.output_gain.next(&self.output, self.sample_rate);
+            if self.fade.is_paused() && self.fade_gain.is_silent() {
+                // Sound is stopped and faded out
+                self.played.store(true, Ordering::Release);
+                return None;
+            }
+            if self.output.is_paused() && self.output_gain.is_silent() {
+                // Output is about to be paused, keep samples until it is resumed
+                return Some(<f32 as Sample>::zero_value());
+            }
+            self.gain = self.sound_gain * fade * output;
+        }
+        self.position = (self.position + 1) % self.channels.max(1);
+
         if let Ok(sample) = self.buffer.pop() {
-            return Some(sample);
+            return Some(sample.to_f32() * self.gain);
         }
         // Producer was dropped. Usually it means end of file
         if self.buffer.is_abandoned() {
+            self.played.store(true, Ordering::Release);
             return None;
         }
         // Reaching here means buffer underrun condition. Producing silence
-        Some(<i16 as Sample>::zero_value())
+        Some(<f32 as Sample>::zero_value())
     }
 }
 
@@ -120,30 +608,50 @@ impl Source for SoundConsumer {
 }
 
 #[cfg(test)]
+// Some of the tests decode WAV files
+#[cfg_attr(not(feature = "wav"), allow(unused_imports, dead_code))]
 mod test {
-    use std::time::Duration;
+    use std::collections::VecDeque;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
 
     use rodio::{OutputStream, Sample, Sink};
     use rodio::buffer::SamplesBuffer;
+    use rodio::cpal::Sample as _;
     use rtrb::RingBuffer;
 
     use crate::OrbSoundSystemError;
-    use crate::system::sound::{SoundConsumer, SoundProducer};
+    use crate::handle::{Repeat, SoundSource};
+    use crate::system::fade::Fader;
+    use crate::system::sound::{Playback, RepeatedReader, SoundConsumer, SoundProducer};
+
+    #[test]
+    #[cfg(feature = "wav")]
+    fn open() {
+        let file = |path: &str| SoundSource::File(path.to_string());
+        assert!(super::open(&file("sounds/test.wav")).is_ok());
+        assert!(matches!(
+            super::open(&file("sounds/missing.wav")),
+            Err(OrbSoundSystemError::SoundFileErr(_))
+        ));
+        assert!(matches!(
+            super::open(&file("Cargo.toml")),
+            Err(OrbSoundSystemError::SoundFileErr(_))
+        ));
+        let data = std::fs::read("sounds/test.wav").unwrap();
+        assert!(super::open(&SoundSource::Bytes(data.into())).is_ok());
+    }
 
     #[test]
     fn source_iterator() {
         let (mut producer, consumer) = RingBuffer::new(2);
-        let mut source = SoundConsumer {
-            buffer: consumer,
-            channels: 0,
-            sample_rate: 0
-        };
+        let mut source = mock_consumer(consumer, 1);
         producer.push(1).unwrap();
         producer.push(2).unwrap();
-        assert_eq!(source.next(), Some(1));
-        assert_eq!(source.next(), Some(2));
+        assert_eq!(source.next(), Some(1i16.to_f32()));
+        assert_eq!(source.next(), Some(2i16.to_f32()));
         // buffer underrun
-        assert_eq!(source.next(), Some(<i16 as Sample>::zero_value()));
+        assert_eq!(source.next(), Some(<f32 as Sample>::zero_value()));
         drop(producer);
         assert_eq!(source.next(), None);
     }
@@ -152,30 +660,305 @@ mod test {
     fn fill_buffer() {
         let reader = SamplesBuffer::new(2, 1, vec![1i16; 15]);
         let (producer, consumer) = RingBuffer::new(10);
-        let mut source = SoundConsumer {
-            buffer: consumer,
-            channels: 0,
-            sample_rate: 0
-        };
+        let mut source = mock_consumer(consumer, 1);
         let mut sound = SoundProducer {
             reader,
-            buffer: producer
+            buffer: producer,
+            channels: 2,
+            sample_rate: 1,
+            played: source.played.clone(),
+            fade: source.fade.clone(),
+            fade_out: None,
+            written: 0,
+            history: VecDeque::new(),
         };
         let out_of_data = sound.fill_buffer();
         assert!(!out_of_data);
         assert_eq!(source.buffer.slots(), 10);
         for _ in 0..10 {
-            assert_eq!(source.next(), Some(1));
+            assert_eq!(source.next(), Some(1i16.to_f32()));
         }
-        assert_eq!(source.next(), Some(<i16 as Sample>::zero_value()));
+        assert_eq!(source.next(), Some(<f32 as Sample>::zero_value()));
         let out_of_data = sound.fill_buffer();
         assert!(out_of_data);
         assert_eq!(source.buffer.slots(), 5);
-        drop(sound);
+        let now = Instant::now();
+        let tail = sound.into_tail(now);
+        // 5 samples of stereo 1Hz sound
+        assert_eq!(tail.ends_at(), now + Duration::from_millis(2500));
         for _ in 0..5 {
-            assert_eq!(source.next(), Some(1));
+            assert_eq!(source.next(), Some(1i16.to_f32()));
         }
+        assert!(!tail.is_played());
         assert_eq!(source.next(), None);
+        assert!(tail.is_played());
+    }
+
+    #[test]
+    fn refill_in() {
+        let reader = SamplesBuffer::new(2, 1000, vec![1i16; 100]);
+        let (producer, mut consumer) = RingBuffer::new(40);
+        let mut sound = SoundProducer {
+            reader,
+            buffer: producer,
+            channels: 2,
+            sample_rate: 1000,
+            played: Default::default(),
+            fade: Arc::new(Fader::new(1.0)),
+            fade_out: None,
+            written: 0,
+            history: VecDeque::new(),
+        };
+        assert_eq!(sound.refill_in(), Duration::ZERO);
+        sound.fill_buffer();
+        // 40 samples of stereo 1kHz sound is 20ms, half of it should be consumed before refill
+        assert_eq!(sound.refill_in(), Duration::from_millis(10));
+        for _ in 0..20 {
+            consumer.pop().unwrap();
+        }
+        assert_eq!(sound.refill_in(), Duration::from_millis(5));
+        assert_eq!(sound.elapsed(), Duration::from_millis(10));
+        sound.fill_buffer();
+        assert_eq!(sound.elapsed(), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn fade_out() {
+        let reader = Playback::Once(Box::new(SamplesBuffer::new(1, 1000, vec![1000i16; 100])));
+        let (producer, mut consumer) = RingBuffer::new(100);
+        let mut sound = SoundProducer {
+            reader,
+            buffer: producer,
+            channels: 1,
+            sample_rate: 1000,
+            played: Default::default(),
+            fade: Arc::new(Fader::new(1.0)),
+            fade_out: None,
+            written: 0,
+            history: VecDeque::new(),
+        };
+        // 4ms of mono 1kHz sound is 4 samples
+        sound.fade_out(Duration::from_millis(4));
+        let finished = sound.fill_buffer();
+        assert!(finished);
+        assert_eq!(consumer.slots(), 4);
+        for sample in [750, 500, 250, 0] {
+            assert_eq!(consumer.pop(), Ok(sample));
+        }
+        // resumed sound starts from the sample played when fade out started
+        assert_eq!(sound.into_reader().count(), 100);
+    }
+
+    #[test]
+    fn resume() {
+        let samples: Vec<i16> = (0..100).collect();
+        let reader = || Playback::Once(Box::new(SamplesBuffer::new(2, 1000, samples.clone())));
+        let sound = |reader| {
+            let (producer, consumer) = RingBuffer::new(20);
+            let sound = SoundProducer {
+                reader,
+                buffer: producer,
+                channels: 2,
+                sample_rate: 1000,
+                played: Default::default(),
+                fade: Arc::new(Fader::new(1.0)),
+                fade_out: None,
+                written: 0,
+                history: VecDeque::new(),
+            };
+            (sound, consumer)
+        };
+
+        // interrupted in the middle of a frame, resumes from its first sample
+        let (mut interrupted, mut consumer) = sound(reader());
+        interrupted.fill_buffer();
+        for _ in 0..7 {
+            consumer.pop().unwrap();
+        }
+        interrupted.fill_buffer();
+        let resumed: Vec<i16> = interrupted.into_reader().collect();
+        assert_eq!(resumed, samples[6..]);
+
+        // faded out sound resumes from the sample played when fade out started
+        let (mut faded, mut consumer) = sound(reader());
+        faded.fill_buffer();
+        for _ in 0..10 {
+            consumer.pop().unwrap();
+        }
+        faded.fade_out(Duration::from_millis(10));
+        while !faded.fill_buffer() {
+            while consumer.pop().is_ok() {}
+        }
+        let resumed: Vec<i16> = faded.into_reader().collect();
+        assert_eq!(resumed, samples[10..]);
+    }
+
+    #[test]
+    fn gain() {
+        let (mut producer, consumer) = RingBuffer::new(10);
+        let mut source = SoundConsumer::new(
+            consumer,
+            1,
+            1000,
+            Default::default(),
+            2.0,
+            Arc::new(Fader::new(1.0)),
+            Arc::new(Fader::new(1.0)),
+        );
+        for sample in [30000i16, -30000, 1000] {
+            producer.push(sample).unwrap();
+        }
+        // samples near full scale are not clipped, that is left to the output limiter
+        let samples: Vec<f32> = source.by_ref().take(3).collect();
+        assert_eq!(
+            samples,
+            vec![
+                30000i16.to_f32() * 2.0,
+                (-30000i16).to_f32() * 2.0,
+                1000i16.to_f32() * 2.0
+            ]
+        );
+        assert!(samples[0] > 1.8 && samples[1] < -1.8);
+    }
+
+    #[test]
+    fn fades() {
+        let output = Arc::new(Fader::new(1.0));
+        let (mut producer, consumer) = RingBuffer::new(100);
+        let fade = Arc::new(Fader::new(0.0));
+        let mut source = SoundConsumer::new(
+            consumer,
+            2,
+            1000,
+            Default::default(),
+            1.0,
+            fade.clone(),
+            output.clone(),
+        );
+        for _ in 0..100 {
+            producer.push(1000).unwrap();
+        }
+        // fade in during 4 frames, gain changes once per frame
+        fade.set_volume(1.0, Duration::from_millis(4));
+        let samples: Vec<i16> = source.by_ref().map(to_i16).take(10).collect();
+        assert_eq!(
+            samples,
+            vec![250, 250, 500, 500, 750, 750, 1000, 1000, 1000, 1000]
+        );
+
+        // ramp volume of the output
+        output.set_volume(0.5, Duration::from_millis(2));
+        let samples: Vec<i16> = source.by_ref().map(to_i16).take(6).collect();
+        assert_eq!(samples, vec![750, 750, 500, 500, 500, 500]);
+
+        // paused output holds samples once faded out
+        output.pause(Duration::from_millis(1));
+        let slots = source.buffer.slots();
+        assert!(source.by_ref().take(4).all(|sample| sample == 0.0));
+        assert_eq!(source.buffer.slots(), slots);
+        output.resume(Duration::ZERO);
+        assert_eq!(source.next().map(to_i16), Some(500));
+
+        // stopped sound ends once faded out
+        source.next();
+        fade.pause(Duration::from_millis(2));
+        let samples: Vec<i16> = source.by_ref().map(to_i16).take(10).collect();
+        assert_eq!(samples, vec![250, 250]);
+        assert!(source.played.load(std::sync::atomic::Ordering::Acquire));
+    }
+
+    #[test]
+    #[cfg(feature = "wav")]
+    fn repeat() {
+        let source = SoundSource::File("sounds/test.wav".to_string());
+        let reader = super::open(&source).unwrap();
+        let (channels, sample_rate) = (reader.channels() as usize, reader.sample_rate() as usize);
+        let samples: Vec<i16> = reader.collect();
+        let gap = 10 * sample_rate / 1000 * channels;
+
+        // played three times with 10ms of silence between repeats
+        let reader = RepeatedReader::new(
+            source.clone(),
+            super::open(&source).unwrap(),
+            Some(2),
+            Duration::from_millis(10),
+        );
+        let repeated: Vec<i16> = reader.collect();
+        assert_eq!(repeated.len(), samples.len() * 3 + gap * 2);
+        let second = samples.len() + gap;
+        assert_eq!(repeated[..samples.len()], samples[..]);
+        assert!(repeated[samples.len()..second]
+            .iter()
+            .all(|&sample| sample == 0));
+        assert_eq!(repeated[second..second + samples.len()], samples[..]);
+
+        // repeats forever without gaps
+        let reader = RepeatedReader::new(
+            source.clone(),
+            super::open(&source).unwrap(),
+            None,
+            Duration::ZERO,
+        );
+        assert_eq!(reader.take(samples.len() * 5).count(), samples.len() * 5);
+
+        // sound without samples is not repeated
+        let empty = RepeatedReader::new(
+            source,
+            Box::new(SamplesBuffer::new(1, 1000, Vec::<i16>::new())),
+            None,
+            Duration::ZERO,
+        );
+        assert_eq!(empty.count(), 0);
+    }
+
+    #[test]
+    fn repeat_from_cache() {
+        // source is gone, so repeats are played from samples decoded the first time
+        let source = SoundSource::File("sounds/missing.wav".to_string());
+        let samples = || Box::new(SamplesBuffer::new(1, 1000, vec![1i16, 2, 3]));
+        let reader = RepeatedReader::new(source.clone(), samples(), Some(2), Duration::ZERO);
+        assert_eq!(reader.collect::<Vec<_>>(), vec![1, 2, 3, 1, 2, 3, 1, 2, 3]);
+
+        // sound too long to be cached fails once its source can not be opened again
+        let mut reader =
+            Playback::Repeated(RepeatedReader::new(source, samples(), None, Duration::ZERO));
+        if let Playback::Repeated(reader) = &mut reader {
+            reader.cache = None;
+        }
+        let (producer, _consumer) = RingBuffer::new(10);
+        let mut sound = SoundProducer {
+            reader,
+            buffer: producer,
+            channels: 1,
+            sample_rate: 1000,
+            played: Default::default(),
+            fade: Arc::new(Fader::new(1.0)),
+            fade_out: None,
+            written: 0,
+            history: VecDeque::new(),
+        };
+        assert!(sound.fill_buffer());
+        assert_eq!(sound.written, 3);
+        assert!(matches!(
+            sound.take_error(),
+            Some(OrbSoundSystemError::SoundFileErr(_))
+        ));
+    }
+
+    fn to_i16(sample: f32) -> i16 {
+        (sample * i16::MAX as f32).round() as i16
+    }
+
+    fn mock_consumer(buffer: rtrb::Consumer<i16>, channels: u16) -> SoundConsumer {
+        SoundConsumer::new(
+            buffer,
+            channels,
+            1000,
+            Default::default(),
+            1.0,
+            Arc::new(Fader::new(1.0)),
+            Arc::new(Fader::new(1.0)),
+        )
     }
 
     /// Demonstrates usage of ring buffer playing wav file.
@@ -183,9 +966,18 @@ mod test {
     #[ignore]
     fn ring_buffer() {
         let (_stream, stream_handle) =
-            OutputStream::try_default().map_err(|e| OrbSoundSystemError::StreamErr(e)).unwrap();
-        let sink = Sink::try_new(&stream_handle).map_err(|e| OrbSoundSystemError::PlayErr(e)).unwrap();
-        let mut sound = SoundProducer::play("sounds/test.wav", &sink).unwrap();
+            OutputStream::try_default().map_err(OrbSoundSystemError::StreamErr).unwrap();
+        let sink = Sink::try_new(&stream_handle).map_err(OrbSoundSystemError::PlayErr).unwrap();
+        let mut sound = SoundProducer::play(
+            &SoundSource::File("sounds/test.wav".to_string()),
+            &sink,
+            Repeat::Once,
+            Duration::ZERO,
+            1.0,
+            Duration::ZERO,
+            &Arc::new(Fader::new(1.0)),
+        )
+        .unwrap();
         loop {
             let finished = sound.fill_buffer();
             if finished  {
@@ -195,4 +987,4 @@ mod test {
             std::thread::sleep(Duration::from_millis(5));
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,143 @@
+//! State of the system kept across restarts: volume of the whole output, mute state and settings
+//! of layers. Stored in a small text file with one setting per line:
+//!
+//! ```text
+//! volume_db -6
+//! muted false
+//! layer 0.5 false ambient
+//! ```
+//!
+//! Layer line holds volume and pause state of the layer followed by its name. Lines which can not
+//! be parsed are skipped, so a damaged file never prevents the system from starting.
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+// Changed settings are saved after this delay, along with all changes made meanwhile
+pub(crate) const SAVE_DELAY: Duration = Duration::from_secs(1);
+
+/// Settings restored when the system starts.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) struct SavedState {
+    pub volume_db: Option<f32>,
+    pub muted: bool,
+    pub layers: Vec<SavedLayer>,
+}
+
+/// Settings of a layer, see [`SavedState`].
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SavedLayer {
+    pub name: String,
+    pub volume: f32,
+    pub paused: bool,
+}
+
+/// File the state is saved to. Written atomically: new state goes to a temporary file next to it,
+/// which then replaces the old one.
+#[derive(Debug, Clone)]
+pub(crate) struct StateFile {
+    path: PathBuf,
+}
+
+impl StateFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Read saved state. Returns default state if the file does not exist or can not be read.
+    pub fn load(&self) -> SavedState {
+        fs::read_to_string(&self.path)
+            .map(|content| parse(&content))
+            .unwrap_or_default()
+    }
+
+    /// Replace saved state with given one.
+    pub fn save(&self, state: &SavedState) -> io::Result<()> {
+        let mut tmp_name = self.path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp_path = self.path.with_file_name(tmp_name);
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(format(state).as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)
+    }
+}
+
+fn format(state: &SavedState) -> String {
+    let mut content = String::new();
+    if let Some(volume_db) = state.volume_db {
+        content += &format!("volume_db {}\n", volume_db);
+    }
+    content += &format!("muted {}\n", state.muted);
+    for layer in &state.layers {
+        // names spanning several lines can not be stored
+        if !layer.name.contains('\n') {
+            content += &format!("layer {} {} {}\n", layer.volume, layer.paused, layer.name);
+        }
+    }
+    content
+}
+
+fn parse(content: &str) -> SavedState {
+    let mut state = SavedState::default();
+    for line in content.lines() {
+        let mut fields = line.splitn(4, ' ');
+        match (fields.next(), fields.next(), fields.next(), fields.next()) {
+            (Some("volume_db"), Some(value), None, None) => {
+                if let Ok(volume_db) = value.parse() {
+                    state.volume_db = Some(volume_db);
+                }
+            }
+            (Some("muted"), Some(value), None, None) => {
+                if let Ok(muted) = value.parse() {
+                    state.muted = muted;
+                }
+            }
+            (Some("layer"), Some(volume), Some(paused), Some(name)) => {
+                if let (Ok(volume), Ok(paused)) = (volume.parse(), paused.parse()) {
+                    state.layers.push(SavedLayer {
+                        name: name.to_string(),
+                        volume,
+                        paused,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    state
+}
+
+#[cfg(test)]
+mod test {
+    use crate::system::state::{SavedLayer, SavedState, StateFile};
+
+    #[test]
+    fn save_and_load() {
+        let path = std::env::temp_dir().join("orb_sound_state_test");
+        let file = StateFile::new(&path);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(file.load(), SavedState::default());
+
+        let state = SavedState {
+            volume_db: Some(-6.5),
+            muted: true,
+            layers: vec![SavedLayer {
+                name: "ambient sounds".to_string(),
+                volume: 0.5,
+                paused: true,
+            }],
+        };
+        file.save(&state).unwrap();
+        assert_eq!(file.load(), state);
+
+        // damaged lines are skipped
+        std::fs::write(&path, "volume_db loud\nmuted true\nlayer 1.0\n").unwrap();
+        let restored = file.load();
+        assert_eq!(restored.volume_db, None);
+        assert!(restored.muted);
+        assert!(restored.layers.is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,173 @@
+//! Volume of the whole output kept in decibels, so that adjusting it by a fixed step sounds like
+//! the same change at any level.
+
+/// Range of volume of the whole output in decibels, where 0dB leaves sounds as they are recorded.
+/// Set with [`OrbSoundSystemBuilder::volume_limits()`].
+///
+/// [`OrbSoundSystemBuilder::volume_limits()`]: crate::system::OrbSoundSystemBuilder::volume_limits
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VolumeLimits {
+    /// Lowest volume, output is silent at it
+    pub min_db: f32,
+    /// Highest volume, which can only be set explicitly
+    pub max_db: f32,
+    /// Highest volume reachable by adjusting the volume. Adjusting never raises volume above it,
+    /// but does not lower volume which was set above it either
+    pub safe_max_db: f32,
+}
+
+impl Default for VolumeLimits {
+    fn default() -> Self {
+        Self {
+            min_db: -60.0,
+            max_db: 6.0,
+            safe_max_db: 0.0,
+        }
+    }
+}
+
+/// Volume of the whole output along with its mute state. Muting keeps the level, so unmuting
+/// restores it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Volume {
+    limits: VolumeLimits,
+    db: f32,
+    muted: bool,
+}
+
+impl Volume {
+    /// Create volume at 0dB, or at the closest limit if 0dB is out of `limits`.
+    pub fn new(limits: VolumeLimits) -> Self {
+        let mut volume = Self {
+            limits,
+            db: 0.0,
+            muted: false,
+        };
+        volume.set_db(0.0);
+        volume
+    }
+
+    pub fn db(&self) -> f32 {
+        self.db
+    }
+
+    /// Set volume in decibels, limited to the range of the volume.
+    pub fn set_db(&mut self, db: f32) {
+        let VolumeLimits { min_db, max_db, .. } = self.limits;
+        if !db.is_nan() {
+            self.db = db.min(max_db).max(min_db);
+        }
+    }
+
+    /// Set volume as linear gain, 1.0 being 0dB. Zero and negative values mean lowest volume.
+    pub fn set_linear(&mut self, value: f32) {
+        // conversions are done in double precision, so that linear volume reads back unchanged
+        self.set_db(if value > 0.0 {
+            (20.0 * (value as f64).log10()) as f32
+        } else {
+            self.limits.min_db
+        });
+    }
+
+    /// Change volume by `delta_db` decibels. Volume is not raised above safe maximum.
+    pub fn adjust(&mut self, delta_db: f32) {
+        let db = self.db + delta_db;
+        if delta_db > 0.0 {
+            self.set_db(db.min(self.limits.safe_max_db.max(self.db)));
+        } else {
+            self.set_db(db);
+        }
+    }
+
+    /// Change volume by `delta` of linear gain. Volume is not raised above safe maximum.
+    pub fn adjust_linear(&mut self, delta: f32) {
+        let db = self.db;
+        self.set_linear(self.linear() + delta);
+        if delta > 0.0 {
+            self.set_db(self.db.min(self.limits.safe_max_db.max(db)));
+        }
+    }
+
+    /// Returns volume as linear gain, regardless of mute state.
+    pub fn linear(&self) -> f32 {
+        if self.db <= self.limits.min_db {
+            0.0
+        } else {
+            10f64.powf(self.db as f64 / 20.0) as f32
+        }
+    }
+
+    /// Returns gain applied to the output.
+    pub fn gain(&self) -> f32 {
+        if self.muted {
+            0.0
+        } else {
+            self.linear()
+        }
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    /// Silence the output. Returns false if it was muted already.
+    pub fn mute(&mut self) -> bool {
+        !std::mem::replace(&mut self.muted, true)
+    }
+
+    /// Restore volume of muted output. Returns false if it was not muted.
+    pub fn unmute(&mut self) -> bool {
+        std::mem::replace(&mut self.muted, false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::system::volume::{Volume, VolumeLimits};
+
+    #[test]
+    fn volume() {
+        let mut volume = Volume::new(VolumeLimits::default());
+        assert_eq!((volume.db(), volume.gain()), (0.0, 1.0));
+        volume.set_linear(0.5);
+        assert!((volume.db() + 6.02).abs() < 0.01);
+        assert_eq!(volume.linear(), 0.5);
+        volume.set_linear(0.0);
+        assert_eq!((volume.db(), volume.gain()), (-60.0, 0.0));
+        volume.set_db(20.0);
+        assert_eq!(volume.db(), 6.0);
+
+        // adjusting does not exceed safe maximum, nor lowers volume set above it
+        volume.adjust(3.0);
+        assert_eq!(volume.db(), 6.0);
+        volume.adjust(-9.0);
+        assert_eq!(volume.db(), -3.0);
+        volume.adjust(9.0);
+        assert_eq!(volume.db(), 0.0);
+        volume.adjust(-100.0);
+        assert_eq!(volume.db(), -60.0);
+
+        // linear adjustment is limited the same way
+        volume.adjust_linear(0.5);
+        assert_eq!(volume.linear(), 0.5);
+        volume.adjust_linear(2.0);
+        assert_eq!(volume.db(), 0.0);
+        volume.adjust_linear(-2.0);
+        assert_eq!(volume.db(), -60.0);
+    }
+
+    #[test]
+    fn mute() {
+        let mut volume = Volume::new(VolumeLimits::default());
+        volume.set_db(-6.0);
+        assert!(volume.mute());
+        assert!(!volume.mute());
+        assert_eq!(volume.gain(), 0.0);
+        // level is kept while muted
+        volume.adjust(3.0);
+        assert!(volume.unmute());
+        assert!(!volume.unmute());
+        assert_eq!(volume.db(), -3.0);
+        assert!((volume.gain() - 0.708).abs() < 0.001);
+    }
+}